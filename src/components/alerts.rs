@@ -0,0 +1,115 @@
+// Metric alerts - desktop notifications when a metric crosses a configured threshold
+
+use crate::components::system_sensors::SystemPerformanceData;
+use crate::dbus::{NotificationOptions, send_desktop_notification_with};
+use crate::utils::config::{AlertComparison, AlertConfig};
+use crate::utils::{Config, MetricsUnit};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+#[derive(Debug)]
+struct Alert {
+    config: AlertConfig,
+    last_fired: Option<Instant>,
+}
+
+impl Alert {
+    fn urgency(&self) -> u8 {
+        match self.config.importance.as_deref() {
+            Some("low") => 0,
+            Some("normal") => 1,
+            _ => 2,
+        }
+    }
+
+    fn is_breached(&self, value: f32) -> bool {
+        match self.config.comparison {
+            AlertComparison::Below => value < self.config.threshold,
+            AlertComparison::Above => value > self.config.threshold,
+        }
+    }
+
+    fn cooling_down(&self) -> bool {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        self.last_fired
+            .is_some_and(|fired| fired.elapsed() < cooldown)
+    }
+}
+
+/// Checks the configured `[[alert]]` thresholds on every monitoring cycle
+#[derive(Debug)]
+pub struct AlertMonitor {
+    alerts: Vec<Alert>,
+    notification_options: NotificationOptions,
+}
+
+impl AlertMonitor {
+    /// Returns a monitor if at least one alert is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let alerts: Vec<Alert> = config
+            .alert
+            .iter()
+            .flatten()
+            .map(|alert| Alert {
+                config: alert.clone(),
+                last_fired: None,
+            })
+            .collect();
+        if alerts.is_empty() {
+            return None;
+        }
+        Some(Self {
+            alerts,
+            notification_options: NotificationOptions::from_config(config),
+        })
+    }
+
+    /// Notify for every breached threshold that is not cooling down.
+    /// Values are compared in the published `unit`; unavailable metrics are skipped.
+    pub async fn check(&mut self, data: &SystemPerformanceData, unit: MetricsUnit) {
+        let values = match data.to_json(unit) {
+            Ok(values) => values,
+            Err(e) => {
+                warn!("Failed to evaluate metric alerts: {}", e);
+                return;
+            }
+        };
+
+        for alert in &mut self.alerts {
+            let metric = alert.config.metric.as_str();
+            let Some(value) = values.get(metric).and_then(|v| v.as_f64()) else {
+                continue;
+            };
+            let value = value as f32;
+            if !alert.is_breached(value) || alert.cooling_down() {
+                continue;
+            }
+
+            let comparison = match alert.config.comparison {
+                AlertComparison::Below => "below",
+                AlertComparison::Above => "above",
+            };
+            debug!(
+                "Alert on {}: {} is {} {}",
+                metric, value, comparison, alert.config.threshold
+            );
+            let message = format!(
+                "{} is {}, {} the threshold of {}",
+                metric, value, comparison, alert.config.threshold
+            );
+            // Counts as fired even if sending fails, so an unreachable
+            // notification daemon is not retried every cycle
+            alert.last_fired = Some(Instant::now());
+            if let Err(e) = send_desktop_notification_with(
+                "System alert",
+                &message,
+                alert.urgency(),
+                &self.notification_options,
+            )
+            .await
+            {
+                error!("Failed to send alert notification for {}: {}", metric, e);
+            }
+        }
+    }
+}
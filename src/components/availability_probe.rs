@@ -0,0 +1,122 @@
+// Per-entity availability - greys out buttons and switches whose
+// `availability_command` fails, e.g. when the service they control is missing
+
+use crate::components::buttons::{build_command, is_command_allowed};
+use crate::dbus::AvailabilityPayloads;
+use crate::ha_mqtt::MqttClient;
+use crate::utils::{Config, ExecMode};
+use rumqttc::QoS;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Upper bound for a single probe, so a hanging command cannot stall monitoring
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Component id of a configured button or switch
+pub fn entity_id(hostname: &str, name: &str) -> String {
+    format!("{}_{}", hostname, name.replace(" ", "_").to_lowercase())
+}
+
+/// A command whose exit status decides whether an entity is available
+#[derive(Debug, Clone)]
+pub struct AvailabilityProbe {
+    pub entity_id: String,
+    pub command: String,
+    pub exec_mode: ExecMode,
+    pub availability_topic: String,
+}
+
+impl AvailabilityProbe {
+    fn new(
+        config: &Config,
+        platform: &str,
+        name: &str,
+        command: &str,
+        exec_mode: Option<ExecMode>,
+    ) -> Option<Self> {
+        if let Some(allowed) = &config.allowed_commands
+            && !is_command_allowed(command, allowed)
+        {
+            warn!(
+                "Security: ignoring availability_command '{}' of '{}' as its binary is not in allowed_commands",
+                command, name
+            );
+            return None;
+        }
+
+        let entity_id = entity_id(&config.hostname, name);
+        Some(Self {
+            availability_topic: format!("homeassistant/{}/{}/availability", platform, entity_id),
+            entity_id,
+            command: command.to_string(),
+            exec_mode: exec_mode.unwrap_or(config.exec_mode),
+        })
+    }
+
+    /// Run the command; the entity is available if it exits successfully in time
+    pub async fn is_available(&self) -> bool {
+        let mut command = match build_command(&self.command, None, self.exec_mode, None) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!(
+                    "Invalid availability_command for '{}': {}",
+                    self.entity_id, e
+                );
+                return false;
+            }
+        };
+        command.kill_on_drop(true);
+
+        match tokio::time::timeout(PROBE_TIMEOUT, command.output()).await {
+            Ok(Ok(output)) => output.status.success(),
+            Ok(Err(e)) => {
+                debug!("Availability probe for '{}' failed: {}", self.entity_id, e);
+                false
+            }
+            Err(_) => {
+                warn!(
+                    "Availability probe for '{}' timed out after {}s",
+                    self.entity_id,
+                    PROBE_TIMEOUT.as_secs()
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Collect the probes of all buttons and switches with an `availability_command`
+pub fn availability_probes(config: &Config) -> Vec<AvailabilityProbe> {
+    let buttons = config.button.iter().flatten().filter_map(|button| {
+        let command = button.availability_command.as_deref()?;
+        AvailabilityProbe::new(config, "button", &button.name, command, button.exec_mode)
+    });
+    let switches = config.switch.iter().flatten().filter_map(|switch| {
+        let command = switch.availability_command.as_deref()?;
+        AvailabilityProbe::new(config, "switch", &switch.name, command, switch.exec_mode)
+    });
+
+    buttons.chain(switches).collect()
+}
+
+/// Run every probe and publish the resulting availability
+pub async fn publish_probe_availability(
+    client: &MqttClient,
+    probes: &[AvailabilityProbe],
+    payloads: &AvailabilityPayloads,
+) {
+    for probe in probes {
+        let payload = payloads.payload(probe.is_available().await);
+        debug!("Entity '{}' availability: {}", probe.entity_id, payload);
+
+        if let Err(e) = client
+            .publish(&probe.availability_topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!(
+                "Failed to publish availability of '{}': {}",
+                probe.entity_id, e
+            );
+        }
+    }
+}
@@ -1,75 +1,281 @@
+use crate::components::availability_probe::entity_id;
+use crate::components::confirmation::Confirmation;
+use crate::error::{Error, Result};
 use crate::ha_mqtt::HomeAssistantComponent;
-use crate::utils::Config;
-use rumqttc::{AsyncClient, QoS};
-use tracing::{debug, error, info};
-
-pub async fn execute_command(command: &str) -> Result<String, Box<dyn std::error::Error>> {
-    debug!("Executing command: {}", command);
-    let output = tokio::process::Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .await?;
+use crate::utils::{Config, ExecMode};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
 
-    if output.status.success() {
-        let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        debug!("Command output: {}", result);
-        Ok(result)
-    } else {
-        let error_msg = format!("Command failed with exit code: {:?}", output.status.code());
-        debug!(
-            "Command stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        Err(error_msg.into())
+/// Default cap on the stdout and stderr captured from each command
+pub const DEFAULT_MAX_COMMAND_OUTPUT: usize = 64 * 1024;
+
+/// Bytes of stderr kept in the error of a failed command
+const MAX_ERROR_STDERR: usize = 512;
+
+/// Characters that let a shell run something other than the checked binary
+const SHELL_METACHARACTERS: &[char] = &[';', '&', '|', '`', '$', '(', ')', '<', '>', '\n', '\r'];
+
+/// Check whether the binary of `command` appears on the allowlist.
+/// Entries may be either a bare binary name or a full path. Commands with
+/// shell metacharacters or a leading variable assignment are never allowed,
+/// as in shell mode they could run binaries the allowlist does not name.
+pub fn is_command_allowed(command: &str, allowed_commands: &[String]) -> bool {
+    if command.contains(SHELL_METACHARACTERS) {
+        return false;
+    }
+    let Some(binary) = command.split_whitespace().next() else {
+        return false;
+    };
+    if binary.contains('=') {
+        return false;
     }
+    let binary_name = std::path::Path::new(binary)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(binary);
+
+    allowed_commands
+        .iter()
+        .any(|allowed| allowed == binary || allowed == binary_name)
 }
 
-pub async fn handle_button_press(
-    topic: &str,
-    payload: &str,
-    button_topics: &[(String, String)],
-) -> bool {
-    for (button_topic, exec_command) in button_topics {
-        if topic == button_topic && payload.trim() == "PRESS" {
-            info!(
-                "Button press detected on topic '{}', executing: {}",
-                topic, exec_command
-            );
-
-            match execute_command(exec_command).await {
-                Ok(output) => {
-                    info!("Command executed successfully: {}", output);
-                }
-                Err(e) => {
-                    error!("Failed to execute command '{}': {}", exec_command, e);
+/// Values for the `{hostname}`, `{timestamp}` and `{payload}` placeholders in commands
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    pub hostname: String,
+    pub payload: String,
+    /// Unix seconds when the command was triggered
+    pub timestamp: u64,
+}
+
+impl Placeholders {
+    /// Placeholders for a command triggered now by `payload`
+    pub fn new(hostname: &str, payload: &str) -> Self {
+        Self {
+            hostname: hostname.to_string(),
+            payload: payload.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// (placeholder, value) of each placeholder, in positional parameter order
+    fn entries(&self) -> [(&'static str, String); 3] {
+        [
+            ("{hostname}", self.hostname.clone()),
+            ("{timestamp}", self.timestamp.to_string()),
+            ("{payload}", self.payload.clone()),
+        ]
+    }
+
+    /// Substitute the values into a single argv element
+    fn substitute(&self, arg: &str) -> String {
+        self.entries()
+            .iter()
+            .fold(arg.to_string(), |arg, (placeholder, value)| {
+                arg.replace(placeholder, value)
+            })
+    }
+}
+
+/// Build the process for `command` according to `mode`, appending `extra_arg` if given.
+/// In direct mode the string is split into argv with shell quoting rules, but no
+/// shell is involved, so the extra argument can never be interpreted as shell syntax.
+///
+/// With `placeholders`, direct mode substitutes the values into the arguments
+/// (never the program). Shell mode passes them as positional parameters after
+/// the script and replaces each placeholder with `${1}`, `${2}` or `${3}`, so the
+/// shell never parses the values themselves. Like any parameter expansion, these
+/// do not expand inside single quotes.
+pub fn build_command(
+    command: &str,
+    extra_arg: Option<&str>,
+    mode: ExecMode,
+    placeholders: Option<&Placeholders>,
+) -> Result<tokio::process::Command> {
+    match mode {
+        ExecMode::Shell => {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.arg("-c");
+            let mut script = command.to_string();
+            let mut parameters = Vec::new();
+            if let Some(placeholders) = placeholders {
+                for (position, (placeholder, value)) in
+                    placeholders.entries().into_iter().enumerate()
+                {
+                    script = script.replace(placeholder, &format!("${{{}}}", position + 1));
+                    parameters.push(value);
                 }
             }
-            return true;
+            match extra_arg {
+                Some(arg) => cmd.arg(format!("{} {}", script, arg)),
+                None => cmd.arg(script),
+            };
+            // The first argument after the script becomes $0
+            if !parameters.is_empty() {
+                cmd.arg("sh").args(parameters);
+            }
+            Ok(cmd)
+        }
+        ExecMode::Direct => {
+            let argv = shell_words::split(command)
+                .map_err(|e| Error::Command(format!("Failed to parse '{}': {}", command, e)))?;
+            let (program, args) = argv
+                .split_first()
+                .ok_or_else(|| Error::Command("Empty command".to_string()))?;
+            let mut cmd = tokio::process::Command::new(program);
+            match placeholders {
+                Some(placeholders) => cmd.args(args.iter().map(|arg| placeholders.substitute(arg))),
+                None => cmd.args(args),
+            };
+            if let Some(arg) = extra_arg {
+                cmd.arg(arg);
+            }
+            Ok(cmd)
+        }
+    }
+}
+
+/// Working directory and extra environment of a configured command
+#[derive(Debug, Clone, Default)]
+pub struct CommandContext {
+    pub working_dir: Option<PathBuf>,
+    pub env: HashMap<String, String>,
+}
+
+impl CommandContext {
+    pub fn new(working_dir: Option<PathBuf>, env: HashMap<String, String>) -> Self {
+        Self { working_dir, env }
+    }
+
+    /// Run `cmd` in the working directory with the extra environment on top
+    /// of the daemon's own
+    pub fn apply(&self, cmd: &mut tokio::process::Command) {
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
+        }
+        cmd.envs(&self.env);
+    }
+}
+
+/// Exit status and captured output of a finished command
+pub struct CappedOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CappedOutput {
+    /// Error for a command that failed, with the start of its stderr
+    pub fn exit_error(&self) -> Error {
+        let stderr = self.stderr.trim();
+        let mut end = stderr.len().min(MAX_ERROR_STDERR);
+        while !stderr.is_char_boundary(end) {
+            end -= 1;
         }
+        let mut capped = stderr[..end].to_string();
+        if end < stderr.len() {
+            capped.push_str("...");
+        }
+        Error::CommandExit {
+            code: self.status.code(),
+            stderr: capped,
+        }
+    }
+}
+
+/// Keep the first `limit` bytes of a stream and discard the rest, so the child
+/// never blocks on a full pipe while its output stays bounded in memory
+async fn read_capped<R: AsyncRead + Unpin>(mut reader: R, limit: usize) -> std::io::Result<String> {
+    let mut captured = Vec::new();
+    (&mut reader)
+        .take(limit as u64)
+        .read_to_end(&mut captured)
+        .await?;
+    let discarded = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+
+    let mut text = String::from_utf8_lossy(&captured).into_owned();
+    if discarded > 0 {
+        text.push_str(&format!("... [truncated {} bytes]", discarded));
+    }
+    Ok(text)
+}
+
+/// Run `cmd` to completion like `Command::output`, capturing at most
+/// `max_output` bytes each of stdout and stderr
+pub async fn output_capped(
+    mut cmd: tokio::process::Command,
+    max_output: usize,
+) -> Result<CappedOutput> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let (stdout, stderr, status) = tokio::try_join!(
+        read_capped(stdout, max_output),
+        read_capped(stderr, max_output),
+        child.wait()
+    )?;
+    Ok(CappedOutput {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+pub async fn execute_command(
+    command: &str,
+    mode: ExecMode,
+    context: &CommandContext,
+    placeholders: &Placeholders,
+    max_output: usize,
+) -> Result<String> {
+    debug!("Executing command ({:?}): {}", mode, command);
+    let mut cmd = build_command(command, None, mode, Some(placeholders))?;
+    context.apply(&mut cmd);
+    let output = output_capped(cmd, max_output).await?;
+
+    if output.status.success() {
+        let result = output.stdout.trim().to_string();
+        debug!("Command output: {}", result);
+        Ok(result)
+    } else {
+        debug!("Command stderr: {}", output.stderr);
+        Err(output.exit_error())
     }
-    false
 }
 
 /// Creates button components and returns button topics for subscription
 pub async fn create_button_components_and_setup(
-    client: &AsyncClient,
     config: &Config,
-) -> Result<
-    (Vec<(String, HomeAssistantComponent)>, Vec<(String, String)>),
-    Box<dyn std::error::Error>,
-> {
+) -> Result<(
+    Vec<(String, HomeAssistantComponent)>,
+    Vec<(
+        String,
+        String,
+        String,
+        ExecMode,
+        CommandContext,
+        bool,
+        Option<Confirmation>,
+    )>,
+)> {
     let mut button_components = Vec::new();
     let mut button_topics = Vec::new();
 
     if let Some(buttons) = &config.button {
         debug!("Setting up {} button(s)", buttons.len());
         for button in buttons {
-            let button_id = format!(
-                "{}_{}",
-                config.hostname,
-                button.name.replace(" ", "_").to_lowercase()
-            );
+            let button_id = entity_id(&config.hostname, &button.name);
             let button_topic = format!("homeassistant/button/{}/set", button_id);
 
             // Create component
@@ -77,17 +283,216 @@ pub async fn create_button_components_and_setup(
                 button.name.clone(),
                 button_id.clone(),
                 button_topic.clone(),
-            );
+            )
+            .with_object_id(button.object_id.clone());
 
-            button_components.push((button_id, component));
+            // Buttons needing confirmation get an "Armed" indicator
+            let confirmation = button.confirm.then(|| {
+                Confirmation::new(
+                    button.name.clone(),
+                    button_id.clone(),
+                    Duration::from_secs(config.confirm_window_secs),
+                )
+            });
+            if let Some(confirmation) = &confirmation {
+                button_components.push(confirmation.component());
+            }
 
-            // Subscribe to button command topic
-            debug!("Subscribing to button topic: {}", button_topic);
-            client.subscribe(&button_topic, QoS::AtMostOnce).await?;
+            button_components.push((button_id, component));
 
-            button_topics.push((button_topic, button.exec.clone()));
+            button_topics.push((
+                button_topic,
+                button.name.clone(),
+                button.exec.clone(),
+                button.exec_mode.unwrap_or(config.exec_mode),
+                CommandContext::new(button.working_dir.clone(), button.env.clone()),
+                button
+                    .ignore_retained
+                    .unwrap_or(config.ignore_retained_commands),
+                confirmation,
+            ));
         }
     }
 
     Ok((button_components, button_topics))
 }
+
+/// Creates the built-in button that restarts the daemon if `restart_button`
+/// is enabled, and returns its command topic for subscription
+pub async fn create_restart_button_component_and_setup(
+    config: &Config,
+) -> Result<Option<((String, HomeAssistantComponent), String)>> {
+    if !config.restart_button {
+        return Ok(None);
+    }
+
+    let restart_id = format!("{}_restart_daemon", config.hostname);
+    let restart_topic = format!("{}/restart_daemon/set", config.button_topic);
+
+    let component = HomeAssistantComponent::button(
+        "Restart Daemon".to_string(),
+        restart_id.clone(),
+        restart_topic.clone(),
+    )
+    .with_entity_category("config");
+
+    Ok(Some(((restart_id, component), restart_topic)))
+}
+
+/// Creates the built-in button that reloads the configuration if `reload_button`
+/// is enabled, and returns its command topic for subscription
+pub async fn create_reload_button_component_and_setup(
+    config: &Config,
+) -> Result<Option<((String, HomeAssistantComponent), String)>> {
+    if !config.reload_button {
+        return Ok(None);
+    }
+
+    let reload_id = format!("{}_reload_config", config.hostname);
+    let reload_topic = format!("{}/reload/set", config.button_topic);
+
+    let component = HomeAssistantComponent::button(
+        "Reload Config".to_string(),
+        reload_id.clone(),
+        reload_topic.clone(),
+    )
+    .with_entity_category("config");
+
+    Ok(Some(((reload_id, component), reload_topic)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["systemctl".to_string(), "/usr/bin/notify-send".to_string()]
+    }
+
+    #[test]
+    fn allowlist_matches_binary_name_or_path() {
+        assert!(is_command_allowed("systemctl suspend", &allowed()));
+        assert!(is_command_allowed("/usr/bin/systemctl suspend", &allowed()));
+        assert!(is_command_allowed("/usr/bin/notify-send hi", &allowed()));
+        assert!(!is_command_allowed("reboot", &allowed()));
+        assert!(!is_command_allowed("", &allowed()));
+    }
+
+    fn argv(cmd: &tokio::process::Command) -> Vec<String> {
+        let cmd = cmd.as_std();
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn direct_mode_splits_quoted_arguments() {
+        let cmd = build_command(
+            "notify-send 'Hello world' \"a \\\"b\\\"\"",
+            None,
+            ExecMode::Direct,
+            None,
+        )
+        .unwrap();
+        assert_eq!(argv(&cmd), ["notify-send", "Hello world", "a \"b\""]);
+    }
+
+    #[test]
+    fn direct_mode_rejects_empty_and_unbalanced_commands() {
+        assert!(build_command("", None, ExecMode::Direct, None).is_err());
+        assert!(build_command("   ", None, ExecMode::Direct, None).is_err());
+        assert!(build_command("echo 'oops", None, ExecMode::Direct, None).is_err());
+    }
+
+    #[test]
+    fn extra_arg_is_appended_in_both_modes() {
+        let cmd = build_command("toggle --state", Some("on"), ExecMode::Direct, None).unwrap();
+        assert_eq!(argv(&cmd), ["toggle", "--state", "on"]);
+
+        let cmd = build_command("toggle --state", Some("on"), ExecMode::Shell, None).unwrap();
+        assert_eq!(argv(&cmd), ["sh", "-c", "toggle --state on"]);
+    }
+
+    #[test]
+    fn shell_mode_passes_placeholders_as_positional_parameters() {
+        let placeholders = Placeholders::new("host", "$(evil)");
+        let cmd =
+            build_command("echo {payload}", None, ExecMode::Shell, Some(&placeholders)).unwrap();
+        let args = argv(&cmd);
+        assert_eq!(args[..3], ["sh", "-c", "echo ${3}"]);
+        assert_eq!(args[3..5], ["sh", "host"]);
+        assert_eq!(args[6], "$(evil)");
+
+        let cmd = build_command(
+            "echo {payload}",
+            None,
+            ExecMode::Direct,
+            Some(&placeholders),
+        )
+        .unwrap();
+        assert_eq!(argv(&cmd), ["echo", "$(evil)"]);
+    }
+
+    #[tokio::test]
+    async fn shell_mode_expands_double_quoted_placeholders() {
+        let placeholders = Placeholders::new("host", "$(echo evil); 'x'");
+        let cmd = build_command(
+            "echo \"from {hostname}: {payload}\"",
+            None,
+            ExecMode::Shell,
+            Some(&placeholders),
+        )
+        .unwrap();
+        let output = output_capped(cmd, DEFAULT_MAX_COMMAND_OUTPUT)
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout.trim(), "from host: $(echo evil); 'x'");
+    }
+
+    #[test]
+    fn failed_command_error_includes_capped_stderr() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let output = |stderr: String| CappedOutput {
+            status: ExitStatus::from_raw(256),
+            stdout: String::new(),
+            stderr,
+        };
+        assert_eq!(
+            output("no such unit\n".to_string())
+                .exit_error()
+                .to_string(),
+            "Command failed with exit code: Some(1): no such unit"
+        );
+        assert_eq!(
+            output(String::new()).exit_error().to_string(),
+            "Command failed with exit code: Some(1)"
+        );
+
+        let Error::CommandExit { code, stderr } = output("é".repeat(MAX_ERROR_STDERR)).exit_error()
+        else {
+            panic!("expected a command exit error");
+        };
+        assert_eq!(code, Some(1));
+        assert!(stderr.len() <= MAX_ERROR_STDERR + 3);
+        assert!(stderr.ends_with("..."));
+    }
+
+    #[test]
+    fn allowlist_rejects_shell_tricks() {
+        for command in [
+            "systemctl suspend; rm -rf ~",
+            "systemctl suspend && curl https://example.com | sh",
+            "systemctl $(evil)",
+            "systemctl `evil`",
+            "systemctl suspend > /etc/passwd",
+            "systemctl suspend\nevil",
+            "X=1 evil",
+            "PATH=/tmp systemctl suspend",
+        ] {
+            assert!(!is_command_allowed(command, &allowed()), "{}", command);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// cgroup v2 limits - container-scoped memory and CPU figures for containerized deployments
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::debug;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Read a single-value cgroup file, treating `max` (no limit) as `None`
+fn read_value(path: &Path) -> Option<u64> {
+    let content = fs::read_to_string(path).ok()?;
+    let value = content.trim();
+    if value == "max" {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Look up a `key value` entry in a flat-keyed file such as `memory.stat`
+fn read_keyed_value(path: &Path, key: &str) -> Option<u64> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name == key {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Memory and CPU limits of the cgroup the daemon runs in
+#[derive(Debug)]
+pub struct CgroupLimits {
+    root: PathBuf,
+    memory_max: Option<u64>,
+    cpu_quota_cores: Option<f32>,
+    last_cpu_sample: Option<(Instant, u64)>,
+}
+
+impl CgroupLimits {
+    /// Detect the limits of the current cgroup.
+    /// Returns `None` when neither a memory nor a CPU limit is set, so the
+    /// caller falls back to host metrics.
+    pub fn detect() -> Option<Self> {
+        let root = PathBuf::from(CGROUP_ROOT);
+        let memory_max = read_value(&root.join("memory.max"));
+        let cpu_quota_cores = Self::read_cpu_quota(&root);
+
+        if memory_max.is_none() && cpu_quota_cores.is_none() {
+            debug!("No cgroup limits found under {}", root.display());
+            return None;
+        }
+
+        debug!(
+            "Detected cgroup limits: memory.max={:?} bytes, cpu quota={:?} cores",
+            memory_max, cpu_quota_cores
+        );
+        Some(Self {
+            root,
+            memory_max,
+            cpu_quota_cores,
+            last_cpu_sample: None,
+        })
+    }
+
+    /// Parse `cpu.max` ("<quota> <period>") into a number of cores
+    fn read_cpu_quota(root: &Path) -> Option<f32> {
+        let content = fs::read_to_string(root.join("cpu.max")).ok()?;
+        let mut parts = content.split_whitespace();
+        let quota = parts.next()?;
+        let period: f32 = parts.next()?.parse().ok()?;
+        if quota == "max" || period <= 0.0 {
+            return None;
+        }
+        let quota: f32 = quota.parse().ok()?;
+        Some(quota / period)
+    }
+
+    /// Returns (total_bytes, available_bytes) for the cgroup, if memory is limited.
+    /// Reclaimable page cache (`inactive_file`) is counted as available, as
+    /// `docker stats` does.
+    pub fn memory(&self) -> Option<(u64, u64)> {
+        let total = self.memory_max?;
+        let current = read_value(&self.root.join("memory.current"))?;
+        let inactive_file =
+            read_keyed_value(&self.root.join("memory.stat"), "inactive_file").unwrap_or(0);
+        let used = current.saturating_sub(inactive_file);
+        Some((total, total.saturating_sub(used)))
+    }
+
+    /// CPU usage as a percentage of the cgroup quota since the previous call.
+    /// Returns `None` if CPU is not limited or on the first call.
+    pub fn cpu_usage(&mut self) -> Option<f32> {
+        let cores = self.cpu_quota_cores?;
+        let usage_usec = read_keyed_value(&self.root.join("cpu.stat"), "usage_usec")?;
+        let now = Instant::now();
+
+        let previous = self.last_cpu_sample.replace((now, usage_usec));
+        let (last_time, last_usage) = previous?;
+        let elapsed_usec = now.duration_since(last_time).as_micros() as f32;
+        if elapsed_usec <= 0.0 {
+            return None;
+        }
+
+        let used_usec = usage_usec.saturating_sub(last_usage) as f32;
+        Some((used_usec / (elapsed_usec * cores) * 100.0).min(100.0))
+    }
+}
@@ -0,0 +1,121 @@
+// Two-step confirmation for buttons with destructive actions - the first press
+// arms the button, and only a second press within the window runs it
+
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use rumqttc::QoS;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Retained ON/OFF state topic of a button's "Armed" binary sensor
+pub fn armed_state_topic(button_id: &str) -> String {
+    format!("homeassistant/binary_sensor/{}_armed/state", button_id)
+}
+
+/// Arming state of a button that needs a second press to run
+#[derive(Debug)]
+pub struct Confirmation {
+    name: String,
+    button_id: String,
+    window: Duration,
+    /// Number and expiry of the current arming, `None` while disarmed
+    armed: Mutex<Option<(u64, Instant)>>,
+    armings: AtomicU64,
+}
+
+impl Confirmation {
+    pub fn new(name: String, button_id: String, window: Duration) -> Self {
+        Self {
+            name,
+            button_id,
+            window,
+            armed: Mutex::new(None),
+            armings: AtomicU64::new(0),
+        }
+    }
+
+    /// Diagnostic binary sensor that is on while the button is armed
+    pub fn component(&self) -> (String, HomeAssistantComponent) {
+        let component_id = format!("{}_armed", self.button_id);
+        let component = HomeAssistantComponent::binary_sensor(
+            format!("{} Armed", self.name),
+            component_id.clone(),
+            armed_state_topic(&self.button_id),
+            None,
+        )
+        .with_entity_category("diagnostic");
+        (component_id, component)
+    }
+
+    /// Handle a press: returns true if the button was armed, which disarms it
+    /// and means the action should run. Otherwise the button is armed until
+    /// the window has passed and false is returned.
+    pub fn press(self: &Arc<Self>, client: &MqttClient) -> bool {
+        let Ok(mut armed) = self.armed.lock() else {
+            return false;
+        };
+        if let Some((_, expires)) = *armed
+            && Instant::now() < expires
+        {
+            *armed = None;
+            info!("'{}' confirmed", self.name);
+            self.spawn_publish_armed(client, false);
+            return true;
+        }
+
+        let arming = self.armings.fetch_add(1, Ordering::AcqRel) + 1;
+        *armed = Some((arming, Instant::now() + self.window));
+        info!(
+            "'{}' armed, press again within {} seconds to confirm",
+            self.name,
+            self.window.as_secs()
+        );
+        self.spawn_publish_armed(client, true);
+
+        // Disarm once the window has passed, unless it was confirmed or
+        // armed again meanwhile
+        let confirmation = Arc::clone(self);
+        let client = client.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(confirmation.window).await;
+            let expired = match confirmation.armed.lock() {
+                Ok(mut armed) if armed.is_some_and(|(current, _)| current == arming) => {
+                    *armed = None;
+                    true
+                }
+                _ => false,
+            };
+            if expired {
+                info!("'{}' disarmed without confirmation", confirmation.name);
+                confirmation.publish_armed(&client, false).await;
+            }
+        });
+        false
+    }
+
+    /// Publish the disarmed state in the background, e.g. at startup
+    pub fn spawn_reset(self: &Arc<Self>, client: &MqttClient) {
+        self.spawn_publish_armed(client, false);
+    }
+
+    fn spawn_publish_armed(self: &Arc<Self>, client: &MqttClient, armed: bool) {
+        let confirmation = Arc::clone(self);
+        let client = client.clone();
+        tokio::spawn(async move {
+            confirmation.publish_armed(&client, armed).await;
+        });
+    }
+
+    async fn publish_armed(&self, client: &MqttClient, armed: bool) {
+        let topic = armed_state_topic(&self.button_id);
+        let payload = if armed { "ON" } else { "OFF" };
+        debug!("Publishing armed state to '{}': {}", topic, payload);
+        if let Err(e) = client
+            .publish(&topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!("Failed to publish armed state of '{}': {}", self.name, e);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Handler counts - diagnostic sensor showing how many command handlers and
+// subscriptions were registered, to check a config remotely
+
+use crate::ha_mqtt::{HandlerCounts, HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use rumqttc::QoS;
+use tracing::{debug, warn};
+
+pub fn handler_counts_state_topic(config: &Config) -> String {
+    format!(
+        "homeassistant/sensor/{}/command_handlers/state",
+        config.hostname
+    )
+}
+
+/// Creates the diagnostic sensor with the number of registered handlers,
+/// with the subscriptions and the count per kind as attributes
+pub fn create_handler_counts_component(config: &Config) -> (String, HomeAssistantComponent) {
+    let component_id = format!("{}_command_handlers", config.hostname);
+    let state_topic = handler_counts_state_topic(config);
+    let component = HomeAssistantComponent::sensor(
+        "Command Handlers".to_string(),
+        component_id.clone(),
+        state_topic.clone(),
+        None,
+        None,
+        Some("{{ value_json.handlers }}".to_string()),
+    )
+    .with_json_attributes(state_topic)
+    .with_entity_category("diagnostic");
+    (component_id, component)
+}
+
+/// Publish the counts, retained, since they only change on startup and reload
+pub async fn publish_handler_counts(client: &MqttClient, state_topic: &str, counts: HandlerCounts) {
+    let payload = match serde_json::to_string(&counts) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("Failed to serialize handler counts: {}", e);
+            return;
+        }
+    };
+    debug!("Publishing handler counts: {}", payload);
+    if let Err(e) = client
+        .publish(state_topic, QoS::AtLeastOnce, true, payload)
+        .await
+    {
+        warn!("Failed to publish handler counts: {}", e);
+    }
+}
@@ -0,0 +1,109 @@
+// System health roll-up - one "problem" binary sensor for all configured thresholds
+
+use crate::components::system_sensors::SystemPerformanceData;
+use crate::error::Result;
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use crate::utils::config::HealthConfig;
+use rumqttc::QoS;
+use tracing::{debug, warn};
+
+fn health_state_topic(config: &Config) -> String {
+    format!(
+        "homeassistant/binary_sensor/{}/health/state",
+        config.hostname
+    )
+}
+
+/// Creates the system health binary sensor if a `[health]` block is configured
+pub fn create_health_component(config: &Config) -> Option<(String, HomeAssistantComponent)> {
+    config.health.as_ref()?;
+
+    let component_id = format!("{}_health", config.hostname);
+    let component = HomeAssistantComponent::binary_sensor(
+        "System Health".to_string(),
+        component_id.clone(),
+        health_state_topic(config),
+        Some("problem".to_string()),
+    );
+    Some((component_id, component))
+}
+
+/// Evaluates the configured thresholds on every monitoring cycle
+#[derive(Debug)]
+pub struct HealthMonitor {
+    thresholds: HealthConfig,
+    state_topic: String,
+    /// Consecutive cycles with CPU load above the threshold
+    cpu_breaches: u32,
+}
+
+impl HealthMonitor {
+    /// Returns a monitor if a `[health]` block is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let thresholds = config.health.clone()?;
+        Some(Self {
+            thresholds,
+            state_topic: health_state_topic(config),
+            cpu_breaches: 0,
+        })
+    }
+
+    /// Describe every breached threshold; an empty list means healthy
+    pub fn evaluate(&mut self, data: &SystemPerformanceData) -> Vec<String> {
+        let mut problems = Vec::new();
+        let thresholds = &self.thresholds;
+
+        if let Some(min) = thresholds.disk_free_min_pct
+            && let Some(free) = data.disk_free_percentage
+            && free < min
+        {
+            problems.push(format!("disk free {:.1}% below {:.1}%", free, min));
+        }
+
+        if let Some(min) = thresholds.memory_free_min_pct
+            && let Some(free) = data.memory_free_percentage
+            && free < min
+        {
+            problems.push(format!("memory free {:.1}% below {:.1}%", free, min));
+        }
+
+        if let Some(max) = thresholds.cpu_load_max_pct {
+            if data.cpu_load > max {
+                self.cpu_breaches += 1;
+            } else {
+                self.cpu_breaches = 0;
+            }
+            // Only sustained load counts, so short spikes do not raise a problem
+            if self.cpu_breaches >= thresholds.cpu_sustained_cycles.max(1) {
+                problems.push(format!(
+                    "CPU load above {:.1}% for {} updates",
+                    max, self.cpu_breaches
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Evaluate the thresholds and publish `ON` (problem) or `OFF`
+    pub async fn publish(
+        &mut self,
+        client: &MqttClient,
+        data: &SystemPerformanceData,
+    ) -> Result<()> {
+        let problems = self.evaluate(data);
+        let payload = if problems.is_empty() {
+            debug!("System health OK");
+            "OFF"
+        } else {
+            warn!("System health problem: {}", problems.join(", "));
+            "ON"
+        };
+
+        client
+            .publish(&self.state_topic, QoS::AtMostOnce, false, payload)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+// Last command - diagnostic sensor with the most recently executed button or
+// switch command and its result, so HA keeps a history of what the daemon did
+
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use rumqttc::QoS;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+fn last_command_state_topic(config: &Config) -> String {
+    format!(
+        "homeassistant/sensor/{}/last_command/state",
+        config.hostname
+    )
+}
+
+/// Creates the diagnostic sensor showing the name of the last executed
+/// command, with its result and time as attributes
+pub fn create_last_command_component(config: &Config) -> (String, HomeAssistantComponent) {
+    let component_id = format!("{}_last_command", config.hostname);
+    let state_topic = last_command_state_topic(config);
+    let component = HomeAssistantComponent::sensor(
+        "Last Command".to_string(),
+        component_id.clone(),
+        state_topic.clone(),
+        None,
+        None,
+        Some("{{ value_json.name }}".to_string()),
+    )
+    .with_json_attributes(state_topic)
+    .with_entity_category("diagnostic");
+    (component_id, component)
+}
+
+#[derive(Serialize, Debug)]
+struct LastCommand<'a> {
+    /// Entity id of the button or switch
+    name: &'a str,
+    /// "button" or "switch"
+    kind: &'a str,
+    /// Switch state the command was run for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a str>,
+    success: bool,
+    /// Unknown for D-Bus actions, commands that did not start and commands
+    /// killed by a signal
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Unix seconds
+    executed_at: u64,
+}
+
+/// Publishes the result of every executed button and switch command
+#[derive(Debug, Clone)]
+pub struct LastCommandReporter {
+    client: MqttClient,
+    state_topic: String,
+}
+
+impl LastCommandReporter {
+    pub fn new(client: MqttClient, config: &Config) -> Self {
+        Self {
+            client,
+            state_topic: last_command_state_topic(config),
+        }
+    }
+
+    /// Publish `result` as the last command, retained so it survives HA restarts
+    pub async fn report(
+        &self,
+        name: &str,
+        kind: &str,
+        payload: Option<&str>,
+        result: &Result<String>,
+    ) {
+        let exit_code = match result {
+            Ok(_) => Some(0),
+            Err(Error::CommandExit { code, .. }) => *code,
+            Err(_) => None,
+        };
+        let executed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let last_command = LastCommand {
+            name,
+            kind,
+            payload,
+            success: result.is_ok(),
+            exit_code,
+            error: result.as_ref().err().map(ToString::to_string),
+            executed_at,
+        };
+
+        let payload = match serde_json::to_string(&last_command) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize last command: {}", e);
+                return;
+            }
+        };
+        debug!("Publishing last command: {}", payload);
+        if let Err(e) = self
+            .client
+            .publish(&self.state_topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!("Failed to publish last command: {}", e);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Recent daemon logs over MQTT - for remote debugging without journal access
+
+use crate::error::Result;
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use crate::utils::logging::log_buffer;
+use rumqttc::QoS;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+pub fn logs_state_topic(config: &Config) -> String {
+    format!("homeassistant/sensor/{}/logs/state", config.hostname)
+}
+
+/// Payload of the logs topic
+#[derive(Serialize)]
+struct LogsMessage {
+    count: usize,
+    /// Oldest first
+    lines: Vec<String>,
+}
+
+/// Creates the "Publish Logs" button and the "Recent Logs" diagnostic sensor,
+/// whose attributes hold the lines, if `log_buffer_lines` is set. Returns the
+/// button's command topic for subscription.
+pub async fn create_logs_components_and_setup(
+    config: &Config,
+) -> Result<Option<(Vec<(String, HomeAssistantComponent)>, String)>> {
+    if config.log_buffer_lines.is_none() {
+        return Ok(None);
+    }
+
+    let state_topic = logs_state_topic(config);
+    let sensor_id = format!("{}_logs", config.hostname);
+    let sensor = HomeAssistantComponent::sensor(
+        "Recent Logs".to_string(),
+        sensor_id.clone(),
+        state_topic.clone(),
+        None,
+        None,
+        Some("{{ value_json.count }}".to_string()),
+    )
+    .with_json_attributes(state_topic)
+    .with_entity_category("diagnostic");
+
+    let button_id = format!("{}_publish_logs", config.hostname);
+    let button_topic = format!("{}/publish_logs/set", config.button_topic);
+    let button = HomeAssistantComponent::button(
+        "Publish Logs".to_string(),
+        button_id.clone(),
+        button_topic.clone(),
+    )
+    .with_entity_category("diagnostic");
+
+    Ok(Some((
+        vec![(sensor_id, sensor), (button_id, button)],
+        button_topic,
+    )))
+}
+
+/// Publish the buffered log lines to `state_topic`; does nothing without a
+/// log buffer or while a previous publish is still running
+pub async fn publish_recent_logs(client: &MqttClient, state_topic: &str) {
+    let Some(buffer) = log_buffer() else {
+        return;
+    };
+    if !buffer.begin_publish() {
+        return;
+    }
+
+    let lines = buffer.snapshot();
+    let message = LogsMessage {
+        count: lines.len(),
+        lines,
+    };
+    let result = match serde_json::to_string(&message) {
+        Ok(json) => {
+            client
+                .publish(state_topic, QoS::AtLeastOnce, false, json)
+                .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    buffer.end_publish();
+
+    match result {
+        Ok(()) => debug!("Published {} log lines", message.count),
+        Err(e) => warn!("Failed to publish recent logs: {}", e),
+    }
+}
@@ -0,0 +1,62 @@
+// Metrics delta filter - skips publishing updates in which no metric changed
+// meaningfully, with a heartbeat so values never go stale for long
+
+use crate::utils::{Config, MetricsMinDelta};
+use std::time::{Duration, Instant};
+
+/// Decides whether a metrics update differs enough from the last published one
+#[derive(Debug)]
+pub struct MetricsDeltaFilter {
+    min_delta: MetricsMinDelta,
+    heartbeat: Duration,
+    /// Values of the last publish, before annotation, and when it happened
+    last_published: Option<(serde_json::Value, Instant)>,
+}
+
+impl MetricsDeltaFilter {
+    /// Returns a filter if `metrics_min_delta` is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.metrics_min_delta.clone().map(|min_delta| Self {
+            min_delta,
+            heartbeat: Duration::from_secs(config.metrics_heartbeat_secs),
+            last_published: None,
+        })
+    }
+
+    /// Whether `values` should be published: on the first update, once the
+    /// heartbeat is due, or if any metric changed beyond its delta or
+    /// became (un)available
+    pub fn should_publish(&self, values: &serde_json::Value) -> bool {
+        let Some((last, published_at)) = &self.last_published else {
+            return true;
+        };
+        if published_at.elapsed() >= self.heartbeat {
+            return true;
+        }
+        let (Some(fields), Some(last_fields)) = (values.as_object(), last.as_object()) else {
+            return true;
+        };
+        if fields.len() != last_fields.len() {
+            return true;
+        }
+
+        fields.iter().any(|(metric, value)| {
+            match (
+                value.as_f64(),
+                last_fields.get(metric).and_then(|v| v.as_f64()),
+            ) {
+                (Some(value), Some(last)) => {
+                    let min_change = f64::from(self.min_delta.for_metric(metric)) / 100.0;
+                    (value - last).abs() > min_change * last.abs()
+                }
+                (None, None) => false,
+                _ => true,
+            }
+        })
+    }
+
+    /// Remember `values` as the last published update
+    pub fn record_published(&mut self, values: serde_json::Value) {
+        self.last_published = Some((values, Instant::now()));
+    }
+}
@@ -1,12 +1,50 @@
 // components module - Contains component implementations for different MQTT entity types
 
+pub mod alerts;
+pub mod availability_probe;
 pub mod buttons;
+pub mod cgroup;
+pub mod confirmation;
+pub mod handler_counts;
+pub mod health;
+pub mod last_command;
+pub mod logs;
+pub mod metrics_delta;
 pub mod notifications;
+pub mod proc_stat;
+pub mod psi;
+pub mod publish_failures;
+pub mod smart;
 pub mod switch;
 pub mod system_sensors;
 
 // Re-export commonly used items for convenience
-pub use buttons::create_button_components_and_setup;
-pub use notifications::create_notification_components_and_setup;
-pub use switch::create_switch_components_and_setup;
-pub use system_sensors::{SystemMonitor, create_system_sensor_components};
+pub use alerts::AlertMonitor;
+pub use availability_probe::{AvailabilityProbe, availability_probes};
+pub use buttons::{
+    create_button_components_and_setup, create_reload_button_component_and_setup,
+    create_restart_button_component_and_setup,
+};
+pub use confirmation::Confirmation;
+pub use handler_counts::{
+    create_handler_counts_component, handler_counts_state_topic, publish_handler_counts,
+};
+pub use health::{HealthMonitor, create_health_component};
+pub use last_command::{LastCommandReporter, create_last_command_component};
+pub use logs::{create_logs_components_and_setup, logs_state_topic, publish_recent_logs};
+pub use metrics_delta::MetricsDeltaFilter;
+pub use notifications::{
+    NotificationAvailability, NotificationDelivery, create_notification_components_and_setup,
+    notification_availability_topic,
+};
+pub use psi::PsiMonitor;
+pub use publish_failures::{PublishFailureTracker, create_publish_failures_component};
+pub use smart::{SmartDevice, create_smart_components, smart_devices};
+pub use switch::{
+    create_switch_components_and_setup, initial_switch_states, publish_initial_switch_states,
+};
+pub use system_sensors::{
+    DiskInfo, FsTypeFilter, SystemCollector, SystemMonitor, SystemPerformanceData,
+    create_refresh_button_component_and_setup, create_system_sensor_components, list_disks,
+    metric_availability_topics, metric_component_ids,
+};
@@ -1,7 +1,19 @@
-use crate::ha_mqtt::HomeAssistantComponent;
+use crate::components::availability_probe::entity_id;
+use crate::dbus::AvailabilityPayloads;
+use crate::dbus::{
+    NotificationOptions, NotificationRetry, send_desktop_notification_retrying,
+    send_desktop_notification_with, test_notification_service,
+};
+use crate::error::Result;
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
 use crate::utils::Config;
-use rumqttc::{AsyncClient, QoS};
+use rumqttc::QoS;
 use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
 /// Notification payload structure expected from Home Assistant
@@ -10,6 +22,10 @@ pub struct NotificationPayload {
     pub summary: String,
     pub message: String,
     pub importance: Option<String>, // low, normal, high, critical
+    /// Freedesktop icon name or absolute path to an icon file
+    pub icon: Option<String>,
+    /// Absolute path to an image shown with the notification, e.g. a camera snapshot
+    pub image: Option<String>,
 }
 
 impl NotificationPayload {
@@ -24,14 +40,190 @@ impl NotificationPayload {
     }
 }
 
+/// Accept an icon if it is a plain freedesktop icon name or a valid file path
+fn sanitize_icon(icon: &str) -> Option<String> {
+    let is_icon_name = !icon.is_empty()
+        && icon
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if is_icon_name {
+        return Some(icon.to_string());
+    }
+    sanitize_file_path(icon)
+}
+
+/// Resolve `path` (optionally a `file://` URI) to an existing regular file.
+/// Relative paths are rejected, as they would resolve against the notification
+/// daemon's working directory rather than ours.
+fn sanitize_file_path(path: &str) -> Option<String> {
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    if !Path::new(path).is_absolute() {
+        warn!(
+            "Ignoring notification file '{}': path must be absolute",
+            path
+        );
+        return None;
+    }
+    match fs::canonicalize(path) {
+        Ok(resolved) if resolved.is_file() => Some(resolved.to_string_lossy().into_owned()),
+        Ok(_) => {
+            warn!("Ignoring notification file '{}': not a regular file", path);
+            None
+        }
+        Err(e) => {
+            warn!("Ignoring notification file '{}': {}", path, e);
+            None
+        }
+    }
+}
+
+/// Per-entity defaults for notifications received on one notify topic
+#[derive(Debug, Clone, Default)]
+pub struct NotificationDefaults {
+    /// Importance used when the payload does not set one
+    pub importance: Option<String>,
+    pub options: NotificationOptions,
+}
+
 /// Send a system notification via D-Bus
 pub async fn send_system_notification(
     summary: &str,
     message: &str,
     urgency: u8,
-) -> Result<(), Box<dyn std::error::Error>> {
-    use crate::dbus::send_desktop_notification;
-    send_desktop_notification(summary, message, urgency).await
+    options: &NotificationOptions,
+) -> Result<()> {
+    send_desktop_notification_with(summary, message, urgency, options).await
+}
+
+/// How often queued notifications check whether the notification service is back
+const QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A notification waiting for the notification service to come back
+#[derive(Debug)]
+struct QueuedNotification {
+    summary: String,
+    message: String,
+    urgency: u8,
+    options: NotificationOptions,
+}
+
+#[derive(Debug, Default)]
+struct NotificationQueue {
+    pending: VecDeque<QueuedNotification>,
+    /// Whether a task is waiting to deliver `pending`
+    flushing: bool,
+}
+
+/// Retries and queueing of the notifications received over MQTT, shared by
+/// all notify entities
+#[derive(Debug, Clone, Default)]
+pub struct NotificationDelivery {
+    retry: NotificationRetry,
+    /// Notifications kept while the service is down; 0 drops them
+    queue_size: usize,
+    queue: Arc<Mutex<NotificationQueue>>,
+}
+
+impl NotificationDelivery {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            retry: NotificationRetry::from_config(config),
+            queue_size: config.notification_queue_size,
+            queue: Arc::default(),
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, NotificationQueue> {
+        // The queue stays consistent even if a holder panicked
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Send a notification with the configured retries. If it still fails and
+    /// queueing is enabled, it is delivered once the service is back instead.
+    pub async fn deliver(
+        &self,
+        summary: &str,
+        message: &str,
+        urgency: u8,
+        options: &NotificationOptions,
+    ) -> Result<()> {
+        let result =
+            send_desktop_notification_retrying(summary, message, urgency, options, self.retry)
+                .await;
+        if result.is_err() && self.queue_size > 0 {
+            self.enqueue(QueuedNotification {
+                summary: summary.to_string(),
+                message: message.to_string(),
+                urgency,
+                options: options.clone(),
+            });
+        }
+        result
+    }
+
+    /// Queue a notification, dropping the oldest one if the queue is full,
+    /// and start waiting for the service unless that already happens
+    fn enqueue(&self, notification: QueuedNotification) {
+        let mut queue = self.queue();
+        if queue.pending.len() >= self.queue_size
+            && let Some(dropped) = queue.pending.pop_front()
+        {
+            warn!(
+                "Notification queue full, dropping notification '{}'",
+                dropped.summary
+            );
+        }
+        info!(
+            "Queueing notification '{}' until the notification service is available",
+            notification.summary
+        );
+        queue.pending.push_back(notification);
+        if !queue.flushing {
+            queue.flushing = true;
+            let delivery = self.clone();
+            tokio::spawn(async move { delivery.flush().await });
+        }
+    }
+
+    /// Wait for the notification service and deliver the queued notifications
+    /// in the order they were received
+    async fn flush(&self) {
+        loop {
+            tokio::time::sleep(QUEUE_CHECK_INTERVAL).await;
+
+            loop {
+                let next = {
+                    let mut queue = self.queue();
+                    match queue.pending.pop_front() {
+                        Some(notification) => notification,
+                        None => {
+                            queue.flushing = false;
+                            return;
+                        }
+                    }
+                };
+                if !test_notification_service(&next.options).await {
+                    debug!("Notification service still unavailable, keeping queued notifications");
+                    self.queue().pending.push_front(next);
+                    break;
+                }
+                if let Err(e) = send_desktop_notification_with(
+                    &next.summary,
+                    &next.message,
+                    next.urgency,
+                    &next.options,
+                )
+                .await
+                {
+                    warn!("Failed to deliver queued notification: {}", e);
+                    self.queue().pending.push_front(next);
+                    break;
+                }
+            }
+        }
+    }
 }
 
 /// Handle notification command from MQTT
@@ -39,6 +231,8 @@ pub async fn handle_notification_command(
     topic: &str,
     payload: &str,
     notification_topic: &str,
+    defaults: &NotificationDefaults,
+    delivery: &NotificationDelivery,
 ) -> bool {
     if topic == notification_topic {
         debug!(
@@ -48,7 +242,10 @@ pub async fn handle_notification_command(
 
         // Try to parse JSON payload
         match serde_json::from_str::<NotificationPayload>(payload) {
-            Ok(notification) => {
+            Ok(mut notification) => {
+                if notification.importance.is_none() {
+                    notification.importance = defaults.importance.clone();
+                }
                 info!(
                     "Processing notification: {} - {} (importance: {:?})",
                     notification.summary, notification.message, notification.importance
@@ -56,13 +253,24 @@ pub async fn handle_notification_command(
 
                 let urgency = notification.get_urgency();
 
+                // Icon and image from the payload take precedence over the entity defaults
+                let mut options = defaults.options.clone();
+                if let Some(icon) = notification.icon.as_deref().and_then(sanitize_icon) {
+                    options.icon = Some(icon);
+                }
+                if let Some(image) = notification.image.as_deref() {
+                    options.image = sanitize_file_path(image);
+                }
+
                 // Send the system notification
-                match send_system_notification(
-                    &notification.summary,
-                    &notification.message,
-                    urgency,
-                )
-                .await
+                match delivery
+                    .deliver(
+                        &notification.summary,
+                        &notification.message,
+                        urgency,
+                        &options,
+                    )
+                    .await
                 {
                     Ok(()) => {
                         info!("Notification sent successfully");
@@ -80,12 +288,14 @@ pub async fn handle_notification_command(
 
                 // Try to send a fallback notification with the raw payload
                 warn!("Sending fallback notification with raw payload");
-                if let Err(e) = send_system_notification(
-                    "MQTT Notification",
-                    payload,
-                    1, // Normal urgency
-                )
-                .await
+                if let Err(e) = delivery
+                    .deliver(
+                        "MQTT Notification",
+                        payload,
+                        1, // Normal urgency
+                        &defaults.options,
+                    )
+                    .await
                 {
                     error!("Failed to send fallback notification: {}", e);
                 }
@@ -96,28 +306,105 @@ pub async fn handle_notification_command(
     false
 }
 
-/// Creates a built-in notification component and returns the notification topic for subscription
+/// Availability topic shared by all notify entities with `notify_availability`
+pub fn notification_availability_topic(config: &Config) -> String {
+    format!(
+        "homeassistant/device/{}/notification_availability",
+        config.hostname
+    )
+}
+
+/// Marks the notify entities unavailable while no notification daemon
+/// answers, checked on every system monitor update
+#[derive(Debug)]
+pub struct NotificationAvailability {
+    topic: String,
+    options: NotificationOptions,
+    payloads: AvailabilityPayloads,
+    /// Last published availability, so it is only sent on change
+    available: Option<bool>,
+}
+
+impl NotificationAvailability {
+    /// Returns a probe if `notify_availability` is enabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.notify_availability.then(|| Self {
+            topic: notification_availability_topic(config),
+            options: NotificationOptions::from_config(config),
+            payloads: AvailabilityPayloads::from_config(config),
+            available: None,
+        })
+    }
+
+    /// Check the notification service and publish its availability, retained, if it changed
+    pub async fn publish(&mut self, client: &MqttClient) {
+        let available = test_notification_service(&self.options).await;
+        if self.available == Some(available) {
+            return;
+        }
+
+        let payload = self.payloads.payload(available);
+        info!("Notification service availability: {}", payload);
+        match client
+            .publish(&self.topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            Ok(()) => self.available = Some(available),
+            Err(e) => warn!("Failed to publish notification availability: {}", e),
+        }
+    }
+}
+
+/// Creates the built-in notification component plus one per `[[notify]]` entry,
+/// and returns the notification topics with their defaults for subscription
 pub async fn create_notification_components_and_setup(
-    client: &AsyncClient,
     config: &Config,
-) -> Result<(Vec<(String, HomeAssistantComponent)>, String), Box<dyn std::error::Error>> {
-    let notification_id = format!("{}_notifications", config.hostname);
-    let notification_topic = format!("homeassistant/notify/{}/command", notification_id);
-
-    // Create the notification component
-    let component = HomeAssistantComponent::notify(
+) -> Result<(
+    Vec<(String, HomeAssistantComponent)>,
+    Vec<(String, NotificationDefaults)>,
+)> {
+    let bus_options = NotificationOptions::from_config(config);
+    let mut entities = vec![(
         "Notifications".to_string(),
-        notification_id.clone(),
-        notification_topic.clone(),
-    );
+        format!("{}_notifications", config.hostname),
+        None,
+        NotificationDefaults {
+            importance: None,
+            options: bus_options.clone(),
+        },
+    )];
+    for notify in config.notify.iter().flatten() {
+        entities.push((
+            notify.name.clone(),
+            entity_id(&config.hostname, &notify.name),
+            notify.object_id.clone(),
+            NotificationDefaults {
+                importance: notify.importance.clone(),
+                options: NotificationOptions {
+                    app_name: notify.app_name.clone(),
+                    icon: notify.icon.clone(),
+                    ..bus_options.clone()
+                },
+            },
+        ));
+    }
 
-    // Subscribe to notification command topic
-    debug!("Subscribing to notification topic: {}", notification_topic);
-    client
-        .subscribe(&notification_topic, QoS::AtMostOnce)
-        .await?;
+    let mut notification_components = Vec::new();
+    let mut notification_topics = Vec::new();
+    for (name, notification_id, object_id, defaults) in entities {
+        let notification_topic = format!("homeassistant/notify/{}/command", notification_id);
 
-    let notification_components = vec![(notification_id, component)];
+        // Create the notification component
+        let component = HomeAssistantComponent::notify(
+            name,
+            notification_id.clone(),
+            notification_topic.clone(),
+        )
+        .with_object_id(object_id);
+
+        notification_components.push((notification_id, component));
+        notification_topics.push((notification_topic, defaults));
+    }
 
-    Ok((notification_components, notification_topic))
+    Ok((notification_components, notification_topics))
 }
@@ -0,0 +1,55 @@
+// /proc/stat CPU load - deterministic alternative to sysinfo's refresh-timed cpu_usage
+
+use std::fs;
+use tracing::debug;
+
+const PROC_STAT: &str = "/proc/stat";
+
+/// Computes CPU load from the aggregate `cpu` line of `/proc/stat`
+#[derive(Debug, Default)]
+pub struct ProcCpuSampler {
+    /// (idle, total) jiffies at the previous sample
+    last: Option<(u64, u64)>,
+}
+
+impl ProcCpuSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read (idle, total) jiffies across all CPUs
+    fn read_times() -> Option<(u64, u64)> {
+        let content = fs::read_to_string(PROC_STAT).ok()?;
+        let line = content.lines().find(|line| line.starts_with("cpu "))?;
+        let fields: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|field| field.parse().ok())
+            .collect();
+        if fields.len() < 4 {
+            return None;
+        }
+
+        // user nice system idle iowait irq softirq steal; guest time is
+        // already included in user and nice
+        let total = fields.iter().take(8).sum();
+        let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+        Some((idle, total))
+    }
+
+    /// CPU load in percent since the previous sample, or since boot on the first call
+    pub fn sample(&mut self) -> Option<f32> {
+        let Some((idle, total)) = Self::read_times() else {
+            debug!("Failed to read CPU times from {}", PROC_STAT);
+            return None;
+        };
+        let (last_idle, last_total) = self.last.replace((idle, total)).unwrap_or((0, 0));
+
+        let total_delta = total.saturating_sub(last_total);
+        if total_delta == 0 {
+            return None;
+        }
+        let busy_delta = total_delta.saturating_sub(idle.saturating_sub(last_idle));
+        Some(busy_delta as f32 / total_delta as f32 * 100.0)
+    }
+}
@@ -0,0 +1,142 @@
+// Pressure stall information - share of time tasks were stalled on CPU,
+// memory or IO, read from /proc/pressure (Linux 4.20+)
+
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use rumqttc::QoS;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+
+/// A resource with a PSI file
+#[derive(Debug)]
+struct PsiResource {
+    /// JSON field in the pressure payload
+    json_field: &'static str,
+    name: &'static str,
+    path: &'static str,
+}
+
+const PSI_RESOURCES: &[PsiResource] = &[
+    PsiResource {
+        json_field: "cpu_pressure",
+        name: "CPU Pressure",
+        path: "/proc/pressure/cpu",
+    },
+    PsiResource {
+        json_field: "memory_pressure",
+        name: "Memory Pressure",
+        path: "/proc/pressure/memory",
+    },
+    PsiResource {
+        json_field: "io_pressure",
+        name: "IO Pressure",
+        path: "/proc/pressure/io",
+    },
+];
+
+/// The resources whose PSI file exists, checked once since it depends on the kernel
+fn available_resources() -> &'static [&'static PsiResource] {
+    static AVAILABLE: OnceLock<Vec<&'static PsiResource>> = OnceLock::new();
+    AVAILABLE.get_or_init(|| {
+        let available: Vec<&'static PsiResource> = PSI_RESOURCES
+            .iter()
+            .filter(|resource| Path::new(resource.path).exists())
+            .collect();
+        if available.is_empty() {
+            warn!(
+                "monitor_psi is enabled but /proc/pressure is not available, skipping PSI sensors"
+            );
+        }
+        available
+    })
+}
+
+/// The `avg10` value of the `some` line: percentage of the last 10 seconds in
+/// which at least one task was stalled on the resource
+fn parse_some_avg10(content: &str) -> Option<f32> {
+    let line = content.lines().find(|line| line.starts_with("some "))?;
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+/// Publishes PSI stall percentages as one JSON object
+#[derive(Debug)]
+pub struct PsiMonitor {
+    state_topic: String,
+    resources: &'static [&'static PsiResource],
+}
+
+impl PsiMonitor {
+    /// Returns a monitor if `monitor_psi` is enabled and the kernel provides PSI
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if !config.monitor_psi {
+            return None;
+        }
+        let resources = available_resources();
+        if resources.is_empty() {
+            return None;
+        }
+        Some(Self {
+            state_topic: format!("{}/pressure/state", config.sensor_topic_base),
+            resources,
+        })
+    }
+
+    /// Creates a sensor per available resource
+    pub fn components(&self, config: &Config) -> Vec<(String, HomeAssistantComponent)> {
+        self.resources
+            .iter()
+            .map(|resource| {
+                let component_id = format!("{}_{}", config.hostname, resource.json_field);
+                let component = HomeAssistantComponent::sensor(
+                    resource.name.to_string(),
+                    component_id.clone(),
+                    self.state_topic.clone(),
+                    None,
+                    Some("%".to_string()),
+                    Some(format!("{{{{ value_json.{} }}}}", resource.json_field)),
+                )
+                .with_suggested_display_precision(Some(1));
+                (component_id, component)
+            })
+            .collect()
+    }
+
+    /// Read and publish the current stall percentages; unreadable files are
+    /// published as null so their sensors become unknown
+    pub async fn publish(&self, client: &MqttClient) {
+        let values: HashMap<&str, Option<f32>> = self
+            .resources
+            .iter()
+            .map(|resource| {
+                let value = fs::read_to_string(resource.path)
+                    .ok()
+                    .and_then(|content| parse_some_avg10(&content));
+                if value.is_none() {
+                    debug!("Failed to read PSI from {}", resource.path);
+                }
+                (resource.json_field, value)
+            })
+            .collect();
+
+        let payload = match serde_json::to_string(&values) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize PSI metrics: {}", e);
+                return;
+            }
+        };
+        debug!("Publishing PSI metrics: {}", payload);
+        if let Err(e) = client
+            .publish(&self.state_topic, QoS::AtMostOnce, false, payload)
+            .await
+        {
+            warn!("Failed to publish PSI metrics: {}", e);
+        }
+    }
+}
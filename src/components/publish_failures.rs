@@ -0,0 +1,114 @@
+// Metrics publish failures - diagnostic counter with an optional desktop alert
+
+use crate::dbus::{NotificationOptions, send_desktop_notification_with};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use rumqttc::QoS;
+use tracing::{debug, error, warn};
+
+fn publish_failures_state_topic(config: &Config) -> String {
+    format!(
+        "homeassistant/sensor/{}/publish_failures/state",
+        config.hostname
+    )
+}
+
+/// Creates the diagnostic sensor counting consecutive metrics publish failures
+pub fn create_publish_failures_component(config: &Config) -> (String, HomeAssistantComponent) {
+    let component_id = format!("{}_publish_failures", config.hostname);
+    let component = HomeAssistantComponent::sensor(
+        "Metrics Publish Failures".to_string(),
+        component_id.clone(),
+        publish_failures_state_topic(config),
+        None,
+        None,
+        None,
+    )
+    .with_entity_category("diagnostic");
+    (component_id, component)
+}
+
+/// Consecutive failed updates after which the daemon reports itself degraded
+const DEGRADED_AFTER_FAILURES: u32 = 3;
+
+/// Tracks consecutive failed metrics updates of the monitoring loop
+#[derive(Debug)]
+pub struct PublishFailureTracker {
+    state_topic: String,
+    alert_threshold: Option<u32>,
+    notification_options: NotificationOptions,
+    consecutive_failures: u32,
+}
+
+impl PublishFailureTracker {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            state_topic: publish_failures_state_topic(config),
+            alert_threshold: config.publish_failure_alert_threshold,
+            notification_options: NotificationOptions::from_config(config),
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Whether metrics publishing has failed often enough in a row to count
+    /// as degraded
+    pub fn is_degraded(&self) -> bool {
+        self.consecutive_failures >= DEGRADED_AFTER_FAILURES
+    }
+
+    /// Reset the counter after a successful update
+    pub async fn record_success(&mut self, client: &MqttClient) {
+        if self.consecutive_failures > 0 {
+            debug!(
+                "Metrics publishing recovered after {} failures",
+                self.consecutive_failures
+            );
+        }
+        self.consecutive_failures = 0;
+        self.publish(client).await;
+    }
+
+    /// Count a failed update and alert once when the threshold is reached
+    pub async fn record_failure(&mut self, client: &MqttClient) {
+        self.consecutive_failures += 1;
+
+        if self.alert_threshold == Some(self.consecutive_failures) {
+            warn!(
+                "Metrics publishing failed {} times in a row",
+                self.consecutive_failures
+            );
+            let message = format!(
+                "Publishing system metrics failed {} times in a row. Check the MQTT broker connection.",
+                self.consecutive_failures
+            );
+            if let Err(e) = send_desktop_notification_with(
+                "Metrics publishing failing",
+                &message,
+                2,
+                &self.notification_options,
+            )
+            .await
+            {
+                error!("Failed to send publish failure notification: {}", e);
+            }
+        }
+
+        // Likely fails too while the broker link is down; the count is
+        // published again on the next successful update
+        self.publish(client).await;
+    }
+
+    async fn publish(&self, client: &MqttClient) {
+        if let Err(e) = client
+            .publish(
+                &self.state_topic,
+                QoS::AtMostOnce,
+                false,
+                self.consecutive_failures.to_string(),
+            )
+            .await
+        {
+            debug!("Failed to publish publish failure count: {}", e);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// SMART disk health - publishes a "problem" binary sensor per configured device
+
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::Config;
+use rumqttc::QoS;
+use tracing::{debug, info, warn};
+
+const SMARTCTL: &str = "smartctl";
+
+/// A block device whose SMART health is monitored
+#[derive(Debug, Clone)]
+pub struct SmartDevice {
+    pub device: String,
+    pub state_topic: String,
+}
+
+/// Short identifier for a device path, e.g. `/dev/nvme0n1` -> `nvme0n1`
+fn device_id(device: &str) -> String {
+    device
+        .rsplit('/')
+        .next()
+        .unwrap_or(device)
+        .replace(' ', "_")
+        .to_lowercase()
+}
+
+/// Check once whether `smartctl` can be executed at all
+fn smartctl_available() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let available = std::process::Command::new(SMARTCTL)
+            .arg("--version")
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        if !available {
+            warn!("monitor_smart is enabled but smartctl is not available, skipping SMART sensors");
+        }
+        available
+    })
+}
+
+/// Returns the devices to monitor, or an empty list if SMART monitoring is
+/// disabled or `smartctl` is not installed
+pub fn smart_devices(config: &Config) -> Vec<SmartDevice> {
+    if !config.monitor_smart || config.smart_devices.is_empty() {
+        return Vec::new();
+    }
+
+    if !smartctl_available() {
+        return Vec::new();
+    }
+
+    config
+        .smart_devices
+        .iter()
+        .map(|device| SmartDevice {
+            device: device.clone(),
+            state_topic: format!(
+                "homeassistant/binary_sensor/{}/smart_{}/state",
+                config.hostname,
+                device_id(device)
+            ),
+        })
+        .collect()
+}
+
+/// Creates a SMART health binary sensor component per monitored device
+pub fn create_smart_components(
+    config: &Config,
+    devices: &[SmartDevice],
+) -> Vec<(String, HomeAssistantComponent)> {
+    devices
+        .iter()
+        .map(|device| {
+            let component_id = format!("{}_smart_{}", config.hostname, device_id(&device.device));
+            let component = HomeAssistantComponent::binary_sensor(
+                format!("Disk Health {}", device.device),
+                component_id.clone(),
+                device.state_topic.clone(),
+                Some("problem".to_string()),
+            );
+            (component_id, component)
+        })
+        .collect()
+}
+
+/// Run `smartctl -H` and return true if the device reports a problem
+pub async fn check_smart_problem(device: &str) -> Result<bool> {
+    let output = tokio::process::Command::new(SMARTCTL)
+        .arg("-H")
+        .arg(device)
+        .output()
+        .await?;
+
+    // Bits 0 and 1 of the exit status mean the command line could not be
+    // parsed or the device could not be opened, so the health is unknown
+    if output.status.code().is_none_or(|code| code & 0b11 != 0) {
+        return Err(Error::Command(format!(
+            "smartctl could not query {}: {}",
+            device,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let healthy = stdout.contains("PASSED") || stdout.contains("Health Status: OK");
+    Ok(!healthy)
+}
+
+/// Check and publish the health of every monitored device
+pub async fn publish_smart_health(client: &MqttClient, devices: &[SmartDevice]) {
+    for device in devices {
+        match check_smart_problem(&device.device).await {
+            Ok(problem) => {
+                let payload = if problem { "ON" } else { "OFF" };
+                if problem {
+                    warn!("SMART reports a problem on {}", device.device);
+                } else {
+                    debug!("SMART health of {} is OK", device.device);
+                }
+                if let Err(e) = client
+                    .publish(&device.state_topic, QoS::AtMostOnce, false, payload)
+                    .await
+                {
+                    warn!(
+                        "Failed to publish SMART health for {}: {}",
+                        device.device, e
+                    );
+                }
+            }
+            Err(e) => info!("Skipping SMART health for {}: {}", device.device, e),
+        }
+    }
+}
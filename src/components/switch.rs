@@ -1,125 +1,67 @@
-use crate::ha_mqtt::{HomeAssistantComponent, handlers::SwitchAction};
-use crate::utils::Config;
+use crate::components::availability_probe::entity_id;
+use crate::components::buttons::{
+    CommandContext, DEFAULT_MAX_COMMAND_OUTPUT, Placeholders, build_command, is_command_allowed,
+    output_capped,
+};
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient, handlers::SwitchAction};
 use crate::utils::config::DBusAction;
-use rumqttc::{AsyncClient, QoS};
-use tracing::{debug, error, info};
+use crate::utils::{Config, ExecMode, SwitchState};
+use rumqttc::QoS;
+use std::time::Duration;
+use tracing::{debug, warn};
 use zbus::Connection;
 
+/// Upper bound for a `state_command`, so a hanging command cannot stall startup
+const STATE_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn switch_state_topic(switch_id: &str) -> String {
+    format!("homeassistant/switch/{}/state", switch_id)
+}
+
 pub async fn execute_switch_command(
     command: &str,
     state: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    debug!("Executing switch command: {} {}", command, state);
-    let output = tokio::process::Command::new("sh")
-        .arg("-c")
-        .arg(&format!("{} {}", command, state))
-        .output()
-        .await?;
+    mode: ExecMode,
+    context: &CommandContext,
+    placeholders: &Placeholders,
+    max_output: usize,
+) -> Result<String> {
+    debug!(
+        "Executing switch command ({:?}): {} {}",
+        mode, command, state
+    );
+    let mut cmd = build_command(command, Some(state), mode, Some(placeholders))?;
+    context.apply(&mut cmd);
+    let output = output_capped(cmd, max_output).await?;
 
     if output.status.success() {
-        let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let result = output.stdout.trim().to_string();
         debug!("Switch command output: {}", result);
         Ok(result)
     } else {
-        let error_msg = format!(
-            "Switch command failed with exit code: {:?}",
-            output.status.code()
-        );
-        debug!(
-            "Switch command stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        Err(error_msg.into())
+        debug!("Switch command stderr: {}", output.stderr);
+        Err(output.exit_error())
     }
 }
 
-pub async fn handle_switch_command(
-    topic: &str,
-    payload: &str,
-    switch_topics: &[(String, String, String)], // (command_topic, state_topic, exec_command)
-    client: &AsyncClient,
-) -> bool {
-    for (command_topic, state_topic, exec_command) in switch_topics {
-        if topic == command_topic {
-            let payload = payload.trim();
-            if payload == "ON" || payload == "OFF" {
-                info!(
-                    "Switch command received on topic '{}': {}, executing: {} {}",
-                    topic,
-                    payload,
-                    exec_command,
-                    payload.to_lowercase()
-                );
-
-                match execute_switch_command(exec_command, &payload.to_lowercase()).await {
-                    Ok(_output) => {
-                        info!("Switch command executed successfully");
-                        // Publish the new state to the state topic
-                        if let Err(e) = client
-                            .publish(state_topic, QoS::AtLeastOnce, true, payload)
-                            .await
-                        {
-                            error!("Failed to publish switch state: {}", e);
-                        } else {
-                            debug!(
-                                "Published switch state '{}' to topic '{}'",
-                                payload, state_topic
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to execute switch command '{}': {}", exec_command, e);
-                        // Publish empty payload to indicate command failure
-                        if let Err(e) = client
-                            .publish(state_topic, QoS::AtLeastOnce, true, "")
-                            .await
-                        {
-                            error!("Failed to publish switch failure state: {}", e);
-                        } else {
-                            debug!(
-                                "Published empty state to topic '{}' due to command failure",
-                                state_topic
-                            );
-                        }
-                    }
-                }
-                return true;
-            } else {
-                debug!(
-                    "Ignoring invalid switch payload '{}' on topic '{}'",
-                    payload, topic
-                );
-            }
-        }
-    }
-    false
-}
-
 /// Creates switch components and returns switch topics for subscription
 pub async fn create_switch_components_and_setup(
-    client: &AsyncClient,
     config: &Config,
-) -> Result<
-    (
-        Vec<(String, HomeAssistantComponent)>,
-        Vec<(String, String, SwitchAction)>,
-    ),
-    Box<dyn std::error::Error>,
-> {
+) -> Result<(
+    Vec<(String, HomeAssistantComponent)>,
+    Vec<(String, String, String, SwitchAction, bool, bool)>,
+)> {
     let mut switch_components = Vec::new();
     let mut switch_topics = Vec::new();
 
     if let Some(switches) = &config.switch {
         debug!("Setting up {} switch(es)", switches.len());
         for switch in switches {
-            let switch_id = format!(
-                "{}_{}",
-                config.hostname,
-                switch.name.replace(" ", "_").to_lowercase()
-            );
+            let switch_id = entity_id(&config.hostname, &switch.name);
 
             let command_topic = format!("homeassistant/switch/{}/set", switch_id);
-            let state_topic = format!("homeassistant/switch/{}/state", switch_id);
+            let state_topic = switch_state_topic(&switch_id);
 
             // Create component
             let component = HomeAssistantComponent::switch(
@@ -127,34 +69,157 @@ pub async fn create_switch_components_and_setup(
                 switch_id.clone(),
                 command_topic.clone(),
                 state_topic.clone(),
-            );
+                switch.is_optimistic(),
+            )
+            .with_object_id(switch.object_id.clone());
 
             switch_components.push((switch_id, component));
 
-            // Subscribe to switch command topic
-            debug!("Subscribing to switch command topic: {}", command_topic);
-            client.subscribe(&command_topic, QoS::AtMostOnce).await?;
-
             // Create the appropriate switch action based on configuration
             let action = if let Some(exec_command) = &switch.exec {
-                SwitchAction::Exec(exec_command.clone())
+                SwitchAction::Exec(
+                    exec_command.clone(),
+                    switch.exec_mode.unwrap_or(config.exec_mode),
+                    CommandContext::new(switch.working_dir.clone(), switch.env.clone()),
+                )
             } else if let Some(dbus_action) = &switch.dbus {
                 SwitchAction::DBus(dbus_action.clone())
             } else {
-                return Err("Switch must have either 'exec' or 'dbus' action".into());
+                return Err(Error::Config(
+                    "Switch must have either 'exec' or 'dbus' action".to_string(),
+                ));
             };
 
-            switch_topics.push((command_topic, state_topic, action));
+            switch_topics.push((
+                command_topic,
+                switch.name.clone(),
+                state_topic,
+                action,
+                switch.retains_state(),
+                switch
+                    .ignore_retained
+                    .unwrap_or(config.ignore_retained_commands),
+            ));
         }
     }
 
     Ok((switch_components, switch_topics))
 }
 
-pub async fn execute_dbus_switch_command(
-    dbus_action: &DBusAction,
-    state: bool,
-) -> Result<String, Box<dyn std::error::Error>> {
+/// How the state of a switch is determined at startup
+#[derive(Debug, Clone)]
+pub struct InitialSwitchState {
+    name: String,
+    state_topic: String,
+    retain_state: bool,
+    state_command: Option<(String, ExecMode, CommandContext)>,
+    default: Option<SwitchState>,
+}
+
+impl InitialSwitchState {
+    /// Run the state command; the switch is ON if it exits successfully
+    async fn read_state(command: &str, mode: ExecMode, context: &CommandContext) -> Result<bool> {
+        let mut cmd = build_command(command, None, mode, None)?;
+        context.apply(&mut cmd);
+        cmd.kill_on_drop(true);
+        let output = tokio::time::timeout(
+            STATE_COMMAND_TIMEOUT,
+            output_capped(cmd, DEFAULT_MAX_COMMAND_OUTPUT),
+        )
+        .await
+        .map_err(|_| {
+            Error::Timeout(format!(
+                "state_command did not finish within {}s",
+                STATE_COMMAND_TIMEOUT.as_secs()
+            ))
+        })??;
+        Ok(output.status.success())
+    }
+
+    /// The state read back with `state_command`, falling back to `initial_state`
+    async fn resolve(&self) -> Option<SwitchState> {
+        if let Some((command, mode, context)) = &self.state_command {
+            match Self::read_state(command, *mode, context).await {
+                Ok(true) => return Some(SwitchState::On),
+                Ok(false) => return Some(SwitchState::Off),
+                Err(e) => warn!(
+                    "Failed to read the initial state of switch '{}': {}",
+                    self.name, e
+                ),
+            }
+        }
+        self.default
+    }
+}
+
+/// Collect the switches whose state is published at startup, i.e. those with
+/// a `state_command` or an `initial_state`
+pub fn initial_switch_states(config: &Config) -> Vec<InitialSwitchState> {
+    let switches = config.switch.iter().flatten();
+    switches
+        .filter(|switch| switch.state_command.is_some() || switch.initial_state.is_some())
+        .map(|switch| {
+            let state_command = switch.state_command.as_ref().filter(|command| {
+                let allowed = config
+                    .allowed_commands
+                    .as_ref()
+                    .is_none_or(|allowed| is_command_allowed(command, allowed));
+                if !allowed {
+                    warn!(
+                        "Security: ignoring state_command '{}' of '{}' as its binary is not in allowed_commands",
+                        command, switch.name
+                    );
+                }
+                allowed
+            });
+            InitialSwitchState {
+                name: switch.name.clone(),
+                state_topic: switch_state_topic(&entity_id(&config.hostname, &switch.name)),
+                retain_state: switch.retains_state(),
+                state_command: state_command.map(|command| {
+                    (
+                        command.clone(),
+                        switch.exec_mode.unwrap_or(config.exec_mode),
+                        CommandContext::new(switch.working_dir.clone(), switch.env.clone()),
+                    )
+                }),
+                default: switch.initial_state,
+            }
+        })
+        .collect()
+}
+
+/// Publish the startup state of each switch, so HA does not show it as
+/// unknown until the first command
+pub async fn publish_initial_switch_states(client: &MqttClient, states: &[InitialSwitchState]) {
+    for state in states {
+        let Some(switch_state) = state.resolve().await else {
+            continue;
+        };
+        debug!(
+            "Initial state of switch '{}': {}",
+            state.name,
+            switch_state.payload()
+        );
+
+        if let Err(e) = client
+            .publish(
+                &state.state_topic,
+                QoS::AtLeastOnce,
+                state.retain_state,
+                switch_state.payload(),
+            )
+            .await
+        {
+            warn!(
+                "Failed to publish the initial state of switch '{}': {}",
+                state.name, e
+            );
+        }
+    }
+}
+
+pub async fn execute_dbus_switch_command(dbus_action: &DBusAction, state: bool) -> Result<String> {
     debug!(
         "Executing D-Bus switch command: service={}, path={}, interface={}, method={}, state={}",
         dbus_action.service, dbus_action.path, dbus_action.interface, dbus_action.method, state
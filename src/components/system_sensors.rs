@@ -1,57 +1,214 @@
-use crate::ha_mqtt::HomeAssistantComponent;
-use crate::utils::Config;
-use rumqttc::{AsyncClient, QoS};
+use crate::components::alerts::AlertMonitor;
+use crate::components::availability_probe::{
+    AvailabilityProbe, availability_probes, publish_probe_availability,
+};
+use crate::components::cgroup::CgroupLimits;
+use crate::components::health::HealthMonitor;
+use crate::components::metrics_delta::MetricsDeltaFilter;
+use crate::components::notifications::NotificationAvailability;
+use crate::components::proc_stat::ProcCpuSampler;
+use crate::components::psi::PsiMonitor;
+use crate::components::publish_failures::PublishFailureTracker;
+use crate::components::smart::{SmartDevice, publish_smart_health, smart_devices};
+use crate::dbus::AvailabilityPayloads;
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
+use crate::utils::{
+    Config, CpuLoadMethod, CpuSource, MetricsPayloadFormat, MetricsTopicMode, MetricsUnit,
+};
+use rumqttc::QoS;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
 use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, RefreshKind, System};
-use tokio::time::{self, Duration};
-use tracing::{debug, error, info};
+use tokio::sync::{Notify, watch};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, warn};
 
 // Constants for magic numbers
-const BYTES_TO_GB: f32 = 1024.0 * 1024.0 * 1024.0;
+// Byte counts are converted in f64, as f32 loses precision on multi-terabyte sizes
+const BYTES_TO_GB: f64 = 1024.0 * 1024.0 * 1024.0;
 const MIN_DISK_SIZE_BYTES: u64 = 1_073_741_824; // 1GB
-const CPU_REFRESH_DELAY_MS: u64 = 200;
+pub const CPU_REFRESH_DELAY_MS: u64 = 200;
 const METRICS_INTERVAL_SECS: u64 = 60;
+/// How long a publish of the monitor may wait for room in the MQTT request
+/// queue before the update is dropped, so a stuck broker cannot stall it
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
 const MHZ_TO_GHZ: f32 = 1000.0;
 
+/// Filesystem types never picked for the disk sensors unless `disk_fs_exclude` is set
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "tmpfs",
+    "devtmpfs",
+    "ramfs",
+    "overlay",
+    "squashfs",
+    "proc",
+    "sysfs",
+    "devpts",
+    "cgroup",
+    "cgroup2",
+    "efivarfs",
+    "autofs",
+    "fuse.portal",
+];
+
+/// Which filesystem types may be selected for the disk sensors
+#[derive(Debug, Clone)]
+pub struct FsTypeFilter {
+    /// Only these types are considered, if set
+    include: Option<Vec<String>>,
+    /// These types are never considered
+    exclude: Vec<String>,
+}
+
+impl Default for FsTypeFilter {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: PSEUDO_FILESYSTEMS.iter().map(|fs| fs.to_string()).collect(),
+        }
+    }
+}
+
+impl FsTypeFilter {
+    pub fn from_config(config: &Config) -> Self {
+        let default = Self::default();
+        Self {
+            include: config.disk_fs_types.clone(),
+            exclude: config.disk_fs_exclude.clone().unwrap_or(default.exclude),
+        }
+    }
+
+    fn accepts(&self, file_system: &str) -> bool {
+        if self.exclude.iter().any(|fs| fs == file_system) {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.iter().any(|fs| fs == file_system),
+            None => true,
+        }
+    }
+}
+
 // Helper function to round values to 2 decimal places
 fn round_to_2dp(value: f32) -> f32 {
     (value * 100.0).round() / 100.0
 }
 
+// Round an f64 value to 2 decimal places before narrowing it to f32
+fn round_f64_to_2dp(value: f64) -> f32 {
+    ((value * 100.0).round() / 100.0) as f32
+}
+
+/// Raw byte counts behind the GB memory and disk fields
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeBytes {
+    pub memory_total: Option<u64>,
+    pub memory_free: Option<u64>,
+    pub disk_total: Option<u64>,
+    pub disk_free: Option<u64>,
+}
+
+/// Metrics of one collection cycle. A `None` field could not be collected
+/// this cycle and is reported as unavailable rather than as zero.
 #[derive(Serialize, Debug, Clone)]
 pub struct SystemPerformanceData {
     pub cpu_load: f32,
     pub cpu_frequency: Option<f32>,
-    pub memory_total: f32,
-    pub memory_free: f32,
-    pub memory_free_percentage: f32,
-    pub disk_total: f32,
-    pub disk_free: f32,
-    pub disk_free_percentage: f32,
+    pub memory_total: Option<f32>,
+    pub memory_free: Option<f32>,
+    pub memory_free_percentage: Option<f32>,
+    pub disk_total: Option<f32>,
+    pub disk_free: Option<f32>,
+    pub disk_free_percentage: Option<f32>,
+    /// Published instead of the GB values with `metrics_unit = "bytes"`
+    #[serde(skip)]
+    pub sizes_bytes: SizeBytes,
 }
 
 impl SystemPerformanceData {
-    /// Helper function to calculate disk metrics in GB from bytes
-    fn calculate_disk_metrics_gb(total_bytes: u64, available_bytes: u64) -> (f32, f32, f32) {
-        let total = total_bytes as f32 / BYTES_TO_GB;
-        let available = available_bytes as f32 / BYTES_TO_GB;
-        let percentage = if total > 0.0 {
-            (available / total) * 100.0
-        } else {
-            0.0
-        };
-        (total, available, percentage)
+    /// Serialize the metrics, with memory and disk sizes in the given unit
+    pub fn to_json(&self, unit: MetricsUnit) -> Result<serde_json::Value> {
+        let mut values = serde_json::to_value(self)?;
+        if unit == MetricsUnit::Bytes
+            && let Some(fields) = values.as_object_mut()
+        {
+            let sizes = self.sizes_bytes;
+            fields.insert("memory_total".into(), sizes.memory_total.into());
+            fields.insert("memory_free".into(), sizes.memory_free.into());
+            fields.insert("disk_total".into(), sizes.disk_total.into());
+            fields.insert("disk_free".into(), sizes.disk_free.into());
+        }
+        Ok(values)
+    }
+
+    /// Sizes in GB and the free percentage, rounded to 2 decimal places.
+    /// `None` when the total is unknown (zero), which some sandboxes report.
+    fn size_metrics_gb(total_bytes: u64, available_bytes: u64) -> Option<(f32, f32, f32)> {
+        if total_bytes == 0 {
+            return None;
+        }
+        let total = total_bytes as f64 / BYTES_TO_GB;
+        let available = available_bytes as f64 / BYTES_TO_GB;
+        let percentage = (available_bytes as f64 / total_bytes as f64) * 100.0;
+        Some((
+            round_f64_to_2dp(total),
+            round_f64_to_2dp(available),
+            round_f64_to_2dp(percentage),
+        ))
+    }
+
+    fn set_memory(&mut self, total_bytes: u64, available_bytes: u64) {
+        let metrics = Self::size_metrics_gb(total_bytes, available_bytes);
+        self.memory_total = metrics.map(|(total, _, _)| total);
+        self.memory_free = metrics.map(|(_, free, _)| free);
+        self.memory_free_percentage = metrics.map(|(_, _, percentage)| percentage);
+        self.sizes_bytes.memory_total = metrics.map(|_| total_bytes);
+        self.sizes_bytes.memory_free = metrics.map(|_| available_bytes);
+    }
+
+    fn set_disk(&mut self, disk_bytes: Option<(u64, u64)>) {
+        let metrics =
+            disk_bytes.and_then(|(total, available)| Self::size_metrics_gb(total, available));
+        self.disk_total = metrics.map(|(total, _, _)| total);
+        self.disk_free = metrics.map(|(_, free, _)| free);
+        self.disk_free_percentage = metrics.map(|(_, _, percentage)| percentage);
+        self.sizes_bytes.disk_total = metrics.and(disk_bytes).map(|(total, _)| total);
+        self.sizes_bytes.disk_free = metrics.and(disk_bytes).map(|(_, free)| free);
+    }
+
+    /// Replace host memory and CPU figures with container-scoped ones where
+    /// the cgroup sets a limit
+    fn apply_cgroup_limits(&mut self, cgroup: &mut CgroupLimits) {
+        if let Some((total, available)) = cgroup.memory() {
+            self.set_memory(total, available);
+        }
+        if let Some(cpu_load) = cgroup.cpu_usage() {
+            self.cpu_load = round_to_2dp(cpu_load);
+        }
     }
 
     /// Create SystemPerformanceData from system and cached disk metrics
     /// This is the primary method that should be used for optimal performance
-    pub fn from_system_and_cached_disk(system: &System, disk_metrics: (f32, f32, f32)) -> Self {
-        // Get CPU metrics - calculate average CPU usage across all cores
-        let cpu_load = if !system.cpus().is_empty() {
-            let total_usage: f32 = system.cpus().iter().map(|cpu| cpu.cpu_usage()).sum();
-            total_usage / system.cpus().len() as f32
-        } else {
-            0.0
+    /// `disk_bytes` is (total, available) of the cached root disk, `None` if
+    /// no disk was found. `cpu_load_method` selects between the averaged
+    /// per-core usage and the load average per core.
+    pub fn from_system_and_cached_disk(
+        system: &System,
+        disk_bytes: Option<(u64, u64)>,
+        cpu_load_method: CpuLoadMethod,
+    ) -> Self {
+        let cores = system.cpus().len();
+        let cpu_load = match cpu_load_method {
+            _ if cores == 0 => 0.0,
+            // Calculate average CPU usage across all cores
+            CpuLoadMethod::Usage => {
+                let total_usage: f32 = system.cpus().iter().map(|cpu| cpu.cpu_usage()).sum();
+                total_usage / cores as f32
+            }
+            // Normalize the 1-minute load average by the core count
+            CpuLoadMethod::Loadavg => (System::load_average().one / cores as f64 * 100.0) as f32,
         };
 
         // Get CPU frequency (if available) and convert to GHz
@@ -62,29 +219,21 @@ impl SystemPerformanceData {
             .filter(|&freq| freq > 0)
             .map(|freq| freq as f32 / MHZ_TO_GHZ);
 
-        // Get memory metrics
-        let total_memory = system.total_memory();
-        let free_memory = system.available_memory();
-
-        // Convert to GB
-        let total_memory_gb = total_memory as f32 / BYTES_TO_GB;
-        let free_memory_gb = free_memory as f32 / BYTES_TO_GB;
-        let free_percentage = (free_memory as f32 / total_memory as f32) * 100.0;
-
-        // Use the provided disk metrics
-        let (disk_total_gb, disk_free_gb, disk_free_percentage) = disk_metrics;
-
         // Return performance data with values rounded to 2 decimal places
-        Self {
+        let mut data = Self {
             cpu_load: round_to_2dp(cpu_load),
             cpu_frequency: cpu_frequency.map(round_to_2dp),
-            memory_total: round_to_2dp(total_memory_gb),
-            memory_free: round_to_2dp(free_memory_gb),
-            memory_free_percentage: round_to_2dp(free_percentage),
-            disk_total: round_to_2dp(disk_total_gb),
-            disk_free: round_to_2dp(disk_free_gb),
-            disk_free_percentage: round_to_2dp(disk_free_percentage),
-        }
+            memory_total: None,
+            memory_free: None,
+            memory_free_percentage: None,
+            disk_total: None,
+            disk_free: None,
+            disk_free_percentage: None,
+            sizes_bytes: SizeBytes::default(),
+        };
+        data.set_memory(system.total_memory(), system.available_memory());
+        data.set_disk(disk_bytes);
+        data
     }
 }
 
@@ -94,6 +243,10 @@ pub struct MetricConfig {
     pub json_field: &'static str,
     pub unit: Option<&'static str>,
     pub device_class: Option<&'static str>,
+    /// Whether HA enables the sensor on discovery; niche metrics start disabled
+    pub enabled_by_default: bool,
+    /// Decimal places HA displays, independent of the published precision
+    pub suggested_display_precision: Option<u8>,
 }
 
 impl MetricConfig {
@@ -108,36 +261,135 @@ impl MetricConfig {
             json_field,
             unit,
             device_class,
+            enabled_by_default: true,
+            suggested_display_precision: None,
         }
     }
+
+    /// Have HA display the value with this many decimal places
+    pub const fn with_display_precision(mut self, precision: u8) -> Self {
+        self.suggested_display_precision = Some(precision);
+        self
+    }
+
+    /// Mark the sensor as disabled in HA until the user enables it
+    pub const fn disabled_by_default(mut self) -> Self {
+        self.enabled_by_default = false;
+        self
+    }
 }
 
 pub const SYSTEM_METRICS: &[MetricConfig] = &[
-    MetricConfig::new("CPU Load", "cpu_load", Some("%"), None),
-    MetricConfig::new("CPU Frequency", "cpu_frequency", Some("GHz"), None),
+    MetricConfig::new("CPU Load", "cpu_load", Some("%"), None).with_display_precision(1),
+    MetricConfig::new("CPU Frequency", "cpu_frequency", Some("GHz"), None)
+        .with_display_precision(2)
+        .disabled_by_default(),
     MetricConfig::new(
         "Memory Total",
         "memory_total",
         Some("GB"),
         Some("data_size"),
-    ),
-    MetricConfig::new("Memory Free", "memory_free", Some("GB"), Some("data_size")),
-    MetricConfig::new("Memory Free %", "memory_free_percentage", Some("%"), None),
-    MetricConfig::new("Disk Total", "disk_total", Some("GB"), Some("data_size")),
-    MetricConfig::new("Disk Free", "disk_free", Some("GB"), Some("data_size")),
-    MetricConfig::new("Disk Free %", "disk_free_percentage", Some("%"), None),
+    )
+    .with_display_precision(1)
+    .disabled_by_default(),
+    MetricConfig::new("Memory Free", "memory_free", Some("GB"), Some("data_size"))
+        .with_display_precision(2),
+    MetricConfig::new("Memory Free %", "memory_free_percentage", Some("%"), None)
+        .with_display_precision(1),
+    MetricConfig::new("Disk Total", "disk_total", Some("GB"), Some("data_size"))
+        .with_display_precision(1)
+        .disabled_by_default(),
+    MetricConfig::new("Disk Free", "disk_free", Some("GB"), Some("data_size"))
+        .with_display_precision(1),
+    MetricConfig::new("Disk Free %", "disk_free_percentage", Some("%"), None)
+        .with_display_precision(1),
 ];
 
-pub struct SystemMonitor {
+/// Unit of a metric, with data sizes reported in bytes when configured
+fn metric_unit(metric: &MetricConfig, unit: MetricsUnit) -> Option<&'static str> {
+    match (metric.device_class, unit) {
+        (Some("data_size"), MetricsUnit::Bytes) => Some("B"),
+        _ => metric.unit,
+    }
+}
+
+/// Wrap a metric value with its unit for the annotated payload format
+fn annotate_value(
+    metric: &MetricConfig,
+    unit: MetricsUnit,
+    value: &serde_json::Value,
+) -> serde_json::Value {
+    serde_json::json!({ "value": value, "unit": metric_unit(metric, unit) })
+}
+
+/// Display precision of a metric; byte counts are whole numbers
+fn metric_display_precision(metric: &MetricConfig, unit: MetricsUnit) -> Option<u8> {
+    match (metric.device_class, unit) {
+        (Some("data_size"), MetricsUnit::Bytes) => Some(0),
+        _ => metric.suggested_display_precision,
+    }
+}
+
+/// Built-in metrics that are not listed in `disabled`
+fn enabled_metrics(disabled: &[String]) -> impl Iterator<Item = &'static MetricConfig> + '_ {
+    SYSTEM_METRICS
+        .iter()
+        .filter(move |metric| !disabled.iter().any(|name| name == metric.json_field))
+}
+
+fn metric_component_id(hostname: &str, metric: &MetricConfig) -> String {
+    format!(
+        "{}_{}",
+        hostname,
+        metric.json_field.replace(' ', "_").to_lowercase()
+    )
+}
+
+/// Availability topic of a single metric, offline while it cannot be collected
+fn metric_availability_topic(sensor_topic_base: &str, metric: &MetricConfig) -> String {
+    format!("{}/{}/availability", sensor_topic_base, metric.json_field)
+}
+
+/// Component ids of all built-in metric sensors, including disabled ones
+pub fn metric_component_ids(config: &Config) -> Vec<String> {
+    SYSTEM_METRICS
+        .iter()
+        .map(|metric| metric_component_id(&config.hostname, metric))
+        .collect()
+}
+
+/// (component id, availability topic) of every enabled metric sensor
+pub fn metric_availability_topics(config: &Config) -> Vec<(String, String)> {
+    enabled_metrics(&config.disabled_metrics)
+        .map(|metric| {
+            (
+                metric_component_id(&config.hostname, metric),
+                metric_availability_topic(&config.sensor_topic_base, metric),
+            )
+        })
+        .collect()
+}
+
+/// Collects system metrics without any MQTT dependency, so it can be used
+/// on its own by other tools
+pub struct SystemCollector {
     system: System,
     disks: Disks,
-    sensor_topic: String,
-    client: AsyncClient,
+    system_refresh_kind: RefreshKind,
+    disk_refresh_kind: DiskRefreshKind,
+    // Delay between the two CPU samples taken by `warm_up_cpu`
+    cpu_warmup: Duration,
+    // Container limits, used instead of host totals when set
+    cgroup: Option<CgroupLimits>,
+    // Replaces sysinfo's CPU usage with /proc/stat deltas when set
+    proc_cpu: Option<ProcCpuSampler>,
+    // Averaged per-core usage or load average per core
+    cpu_load_method: CpuLoadMethod,
     // Cache the root disk index to avoid searching for it on every loop
     root_disk_index: Option<usize>,
 }
 
-impl SystemMonitor {
+impl SystemCollector {
     /// Create system refresh kind configuration
     fn create_system_refresh_kind() -> RefreshKind {
         RefreshKind::nothing()
@@ -150,43 +402,103 @@ impl SystemMonitor {
         DiskRefreshKind::nothing().with_storage()
     }
 
-    /// Create topic string from components
-    fn create_topic(base: &str, component: &str, suffix: &str) -> String {
-        format!("{}/{}/{}", base, component, suffix)
-    }
-
-    pub fn new(sensor_topic_base: String, client: AsyncClient) -> Self {
-        // Use the new RefreshKind API to initialize system with specific refresh kinds
-        let refresh_kind = Self::create_system_refresh_kind();
+    pub fn new() -> Self {
+        // Create the refresh kinds once and reuse them for every collection
+        let system_refresh_kind = Self::create_system_refresh_kind();
+        let disk_refresh_kind = Self::create_disk_refresh_kind();
 
-        let system = System::new_with_specifics(refresh_kind);
+        let system = System::new_with_specifics(system_refresh_kind);
         // Initialize disks with storage-only refresh since we only need space information
-        let disks = Disks::new_with_refreshed_list_specifics(Self::create_disk_refresh_kind());
-        let sensor_topic = Self::create_topic(&sensor_topic_base, "system_performance", "state");
+        let disks = Disks::new_with_refreshed_list_specifics(disk_refresh_kind);
 
         // Find and cache the root disk index once during initialization
-        let root_disk_index = Self::find_root_disk_index(&disks);
+        let root_disk_index = Self::find_root_disk_index(&disks, &FsTypeFilter::default());
 
         debug!("Root disk index: {:?}", root_disk_index);
 
         Self {
             system,
             disks,
-            sensor_topic,
-            client,
+            system_refresh_kind,
+            disk_refresh_kind,
+            cpu_warmup: Duration::from_millis(CPU_REFRESH_DELAY_MS),
+            cgroup: None,
+            proc_cpu: None,
+            cpu_load_method: CpuLoadMethod::default(),
             root_disk_index,
         }
     }
 
-    /// Find the root disk index once during initialization
+    /// Create a collector with the CPU, cgroup and disk settings from the config
+    pub fn from_config(config: &Config) -> Self {
+        Self::new()
+            .with_cpu_source(config.cpu_source)
+            .with_cpu_load_method(config.cpu_load_method)
+            .with_cpu_warmup(Duration::from_millis(config.cpu_warmup_ms))
+            .with_cgroup_aware(config.cgroup_aware)
+            .with_fs_type_filter(&FsTypeFilter::from_config(config))
+    }
+
+    /// Report container-scoped memory and CPU from cgroup v2 limits when enabled.
+    /// Host metrics are kept if no limits are set.
+    pub fn with_cgroup_aware(mut self, cgroup_aware: bool) -> Self {
+        self.cgroup = if cgroup_aware {
+            let limits = CgroupLimits::detect();
+            if limits.is_none() {
+                info!(
+                    "cgroup_aware is enabled but no cgroup limits are set, reporting host metrics"
+                );
+            }
+            limits
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Select where the CPU load comes from. With `CpuSource::Proc` no
+    /// warmup sample is needed.
+    pub fn with_cpu_source(mut self, cpu_source: CpuSource) -> Self {
+        self.proc_cpu = match cpu_source {
+            CpuSource::Sysinfo => None,
+            CpuSource::Proc => Some(ProcCpuSampler::new()),
+        };
+        self
+    }
+
+    /// Report CPU load as averaged per-core usage or as the load average per core
+    pub fn with_cpu_load_method(mut self, cpu_load_method: CpuLoadMethod) -> Self {
+        self.cpu_load_method = cpu_load_method;
+        self
+    }
+
+    /// Select the disk for the disk sensors among filesystems accepted by `filter`
+    pub fn with_fs_type_filter(mut self, filter: &FsTypeFilter) -> Self {
+        self.root_disk_index = Self::find_root_disk_index(&self.disks, filter);
+        debug!("Root disk index: {:?}", self.root_disk_index);
+        self
+    }
+
+    /// Set the delay used by `warm_up_cpu`
+    pub fn with_cpu_warmup(mut self, cpu_warmup: Duration) -> Self {
+        self.cpu_warmup = cpu_warmup;
+        self
+    }
+
+    /// Find the root disk index once during initialization, skipping
+    /// filesystem types rejected by `filter` (tmpfs, overlay, ... by default)
     /// Returns the disk index if found, None otherwise
-    fn find_root_disk_index(disks: &Disks) -> Option<usize> {
-        let disk_list = disks.list();
+    fn find_root_disk_index(disks: &Disks, filter: &FsTypeFilter) -> Option<usize> {
+        let candidates = || {
+            disks
+                .list()
+                .iter()
+                .enumerate()
+                .filter(|(_, disk)| filter.accepts(&disk.file_system().to_string_lossy()))
+        };
 
         // First try to find the root mount point
-        let root_index = disk_list
-            .iter()
-            .enumerate()
+        let root_index = candidates()
             .find(|(_, disk)| {
                 let mount_point = disk.mount_point().to_str().unwrap_or("");
                 (mount_point == "/sysroot" || mount_point == "/")
@@ -199,74 +511,428 @@ impl SystemMonitor {
         }
 
         // Fallback to largest disk
-        disk_list
-            .iter()
-            .enumerate()
+        candidates()
             .filter(|(_, disk)| disk.total_space() >= MIN_DISK_SIZE_BYTES)
             .max_by_key(|(_, disk)| disk.total_space())
             .map(|(idx, _)| idx)
     }
 
-    /// Get disk metrics for the cached root disk
-    /// Returns (total_gb, free_gb, free_percentage)
-    fn get_root_disk_metrics(&self) -> (f32, f32, f32) {
-        if let Some(index) = self.root_disk_index {
-            // Get the disk directly by index - much more efficient than searching
-            if let Some(disk) = self.disks.list().get(index) {
-                return SystemPerformanceData::calculate_disk_metrics_gb(
-                    disk.total_space(),
-                    disk.available_space(),
-                );
-            }
+    /// Get disk sizes for the cached root disk
+    /// Returns (total_bytes, available_bytes), or `None` if the disk is not found
+    fn get_root_disk_metrics(&self) -> Option<(u64, u64)> {
+        // Get the disk directly by index - much more efficient than searching
+        let disk = self.disks.list().get(self.root_disk_index?)?;
+        Some((disk.total_space(), disk.available_space()))
+    }
+
+    /// Take a second CPU sample after a short delay, since CPU usage is
+    /// computed from the difference between two refreshes.
+    /// Call this once before the first `collect` for an accurate CPU reading.
+    pub async fn warm_up_cpu(&mut self) {
+        if let Some(cgroup) = self.cgroup.as_mut() {
+            cgroup.cpu_usage();
+        }
+        // Without a baseline, the first /proc/stat sample is the load since boot
+        if let Some(proc_cpu) = self.proc_cpu.as_mut() {
+            proc_cpu.sample();
         }
+        tokio::time::sleep(self.cpu_warmup).await;
+        self.system.refresh_specifics(self.system_refresh_kind);
+    }
+
+    /// Refresh system and disk information and return the current metrics
+    pub fn collect(&mut self) -> SystemPerformanceData {
+        self.system.refresh_specifics(self.system_refresh_kind);
+        self.disks.refresh_specifics(false, self.disk_refresh_kind);
+
+        // Get disk metrics using the cached root disk
+        let disk_metrics = self.get_root_disk_metrics();
 
-        // Fallback: if cached disk not found, return zeros
-        (0.0, 0.0, 0.0)
+        let mut data = SystemPerformanceData::from_system_and_cached_disk(
+            &self.system,
+            disk_metrics,
+            self.cpu_load_method,
+        );
+        if let Some(cpu_load) = self.proc_cpu.as_mut().and_then(ProcCpuSampler::sample) {
+            data.cpu_load = round_to_2dp(cpu_load);
+        }
+        if let Some(cgroup) = self.cgroup.as_mut() {
+            data.apply_cgroup_limits(cgroup);
+        }
+        data
     }
+}
 
-    pub async fn run_monitoring_loop(&mut self) {
-        // Create the refresh kinds once and reuse them throughout the monitoring loop
-        let system_refresh_kind = Self::create_system_refresh_kind();
-        let disk_refresh_kind = Self::create_disk_refresh_kind();
+/// A disk as seen by the collector, for diagnostics
+#[derive(Debug, Clone)]
+pub struct DiskInfo {
+    pub name: String,
+    pub mount_point: String,
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    /// Whether this is the disk reported by the disk sensors
+    pub selected: bool,
+}
+
+/// List every disk with the one auto-selected for the disk sensors marked
+pub fn list_disks(filter: &FsTypeFilter) -> Vec<DiskInfo> {
+    let disks =
+        Disks::new_with_refreshed_list_specifics(SystemCollector::create_disk_refresh_kind());
+    let root_index = SystemCollector::find_root_disk_index(&disks, filter);
+
+    disks
+        .list()
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| DiskInfo {
+            name: disk.name().to_string_lossy().into_owned(),
+            mount_point: disk.mount_point().to_string_lossy().into_owned(),
+            file_system: disk.file_system().to_string_lossy().into_owned(),
+            total_bytes: disk.total_space(),
+            available_bytes: disk.available_space(),
+            selected: root_index == Some(index),
+        })
+        .collect()
+}
+
+impl Default for SystemCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+/// Periodically collects system metrics and publishes them over MQTT
+pub struct SystemMonitor {
+    collector: SystemCollector,
+    sensor_topic_base: String,
+    sensor_topic: String,
+    topic_mode: MetricsTopicMode,
+    disabled_metrics: Vec<String>,
+    unit: MetricsUnit,
+    payload_format: MetricsPayloadFormat,
+    client: MqttClient,
+    smart_devices: Vec<SmartDevice>,
+    psi: Option<PsiMonitor>,
+    health: Option<HealthMonitor>,
+    alerts: Option<AlertMonitor>,
+    delta_filter: Option<MetricsDeltaFilter>,
+    publish_failures: Option<PublishFailureTracker>,
+    availability_probes: Vec<AvailabilityProbe>,
+    notification_availability: Option<NotificationAvailability>,
+    // Last published availability per metric, so it is only sent on change
+    metric_availability: HashMap<&'static str, bool>,
+    availability_payloads: AvailabilityPayloads,
+    // Notified to publish immediately instead of waiting for the next tick
+    refresh_trigger: Arc<Notify>,
+    // Set while metrics publishing keeps failing
+    degraded: Option<watch::Sender<bool>>,
+    // Time of the last completed update, checked by the main loop's watchdog
+    heartbeat: Option<watch::Sender<Instant>>,
+}
+
+impl SystemMonitor {
+    /// Create topic string from components
+    fn create_topic(base: &str, component: &str, suffix: &str) -> String {
+        format!("{}/{}/{}", base, component, suffix)
+    }
+
+    pub fn new(sensor_topic_base: String, client: MqttClient) -> Self {
+        let sensor_topic = Self::create_topic(&sensor_topic_base, "system_performance", "state");
+
+        Self {
+            collector: SystemCollector::new(),
+            sensor_topic_base,
+            sensor_topic,
+            topic_mode: MetricsTopicMode::default(),
+            disabled_metrics: Vec::new(),
+            unit: MetricsUnit::default(),
+            payload_format: MetricsPayloadFormat::default(),
+            client: client.with_publish_timeout(PUBLISH_TIMEOUT),
+            smart_devices: Vec::new(),
+            psi: None,
+            health: None,
+            alerts: None,
+            delta_filter: None,
+            publish_failures: None,
+            availability_probes: Vec::new(),
+            notification_availability: None,
+            metric_availability: HashMap::new(),
+            availability_payloads: AvailabilityPayloads::default(),
+            refresh_trigger: Arc::new(Notify::new()),
+            degraded: None,
+            heartbeat: None,
+        }
+    }
+
+    /// Publish to the shared performance topic or to one topic per metric
+    pub fn with_topic_mode(mut self, topic_mode: MetricsTopicMode) -> Self {
+        self.topic_mode = topic_mode;
+        self
+    }
+
+    /// Publish memory and disk sizes in GB or raw bytes
+    pub fn with_unit(mut self, unit: MetricsUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Publish metric values as plain numbers or annotated with their unit
+    pub fn with_payload_format(mut self, payload_format: MetricsPayloadFormat) -> Self {
+        self.payload_format = payload_format;
+        self
+    }
+
+    /// Leave these metrics, by JSON field name, out of the published data
+    pub fn with_disabled_metrics(mut self, disabled_metrics: Vec<String>) -> Self {
+        self.disabled_metrics = disabled_metrics;
+        self
+    }
+
+    /// Publish immediately whenever `refresh_trigger` is notified
+    pub fn with_refresh_trigger(mut self, refresh_trigger: Arc<Notify>) -> Self {
+        self.refresh_trigger = refresh_trigger;
+        self
+    }
+
+    /// Report through `degraded` whether metrics publishing keeps failing
+    pub fn with_degraded_signal(mut self, degraded: watch::Sender<bool>) -> Self {
+        self.degraded = Some(degraded);
+        self
+    }
+
+    /// Record the time of every completed update in `heartbeat`, so a wedged
+    /// monitor can be detected
+    pub fn with_heartbeat(mut self, heartbeat: watch::Sender<Instant>) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Publish these payloads to the metric and entity availability topics
+    pub fn with_availability_payloads(mut self, payloads: AvailabilityPayloads) -> Self {
+        self.availability_payloads = payloads;
+        self
+    }
+
+    /// Create a monitor configured from `config`
+    pub fn from_config(config: &Config, client: MqttClient) -> Self {
+        Self::new(config.sensor_topic_base.clone(), client)
+            .with_collector(SystemCollector::from_config(config))
+            .with_smart_devices(smart_devices(config))
+            .with_psi(PsiMonitor::from_config(config))
+            .with_health(HealthMonitor::from_config(config))
+            .with_alerts(AlertMonitor::from_config(config))
+            .with_delta_filter(MetricsDeltaFilter::from_config(config))
+            .with_publish_failures(PublishFailureTracker::from_config(config))
+            .with_availability_probes(availability_probes(config))
+            .with_notification_availability(NotificationAvailability::from_config(config))
+            .with_availability_payloads(AvailabilityPayloads::from_config(config))
+            .with_topic_mode(config.metrics_topic_mode)
+            .with_disabled_metrics(config.disabled_metrics.clone())
+            .with_unit(config.metrics_unit)
+            .with_payload_format(config.metrics_payload_format)
+    }
+
+    /// Collect metrics with `collector` instead of a default one
+    pub fn with_collector(mut self, collector: SystemCollector) -> Self {
+        self.collector = collector;
+        self
+    }
+
+    /// Also evaluate and publish the composite system health on every update
+    pub fn with_health(mut self, health: Option<HealthMonitor>) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Also send desktop notifications for metrics crossing the alert thresholds
+    pub fn with_alerts(mut self, alerts: Option<AlertMonitor>) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// Skip publishing updates that the filter considers unchanged
+    pub fn with_delta_filter(mut self, delta_filter: Option<MetricsDeltaFilter>) -> Self {
+        self.delta_filter = delta_filter;
+        self
+    }
+
+    /// Also run these entity availability probes on every update
+    pub fn with_availability_probes(mut self, probes: Vec<AvailabilityProbe>) -> Self {
+        self.availability_probes = probes;
+        self
+    }
+
+    /// Also check and publish whether the notification service is reachable on every update
+    pub fn with_notification_availability(
+        mut self,
+        notification_availability: Option<NotificationAvailability>,
+    ) -> Self {
+        self.notification_availability = notification_availability;
+        self
+    }
+
+    /// Count consecutive failed updates and publish the count on every update
+    pub fn with_publish_failures(mut self, tracker: PublishFailureTracker) -> Self {
+        self.publish_failures = Some(tracker);
+        self
+    }
+
+    /// Also check and publish SMART health of these devices on every update
+    pub fn with_smart_devices(mut self, smart_devices: Vec<SmartDevice>) -> Self {
+        self.smart_devices = smart_devices;
+        self
+    }
+
+    /// Also publish the CPU, memory and IO pressure stall sensors on every update;
+    /// `None` if `monitor_psi` is off or the kernel has no `/proc/pressure`
+    pub fn with_psi(mut self, psi: Option<PsiMonitor>) -> Self {
+        self.psi = psi;
+        self
+    }
+
+    /// Collect the current metrics without publishing them
+    pub fn collect(&mut self) -> SystemPerformanceData {
+        self.collector.collect()
+    }
+
+    /// Collect and publish a single round of system metrics
+    pub async fn publish_once(&mut self) -> Result<()> {
+        self.collector.warm_up_cpu().await;
+        self.update_system_metrics(true).await
+    }
+
+    pub async fn run_monitoring_loop(&mut self) {
         // For accurate CPU usage, we need to refresh again after a small delay
-        tokio::time::sleep(tokio::time::Duration::from_millis(CPU_REFRESH_DELAY_MS)).await;
-        self.system.refresh_specifics(system_refresh_kind);
+        self.collector.warm_up_cpu().await;
 
-        let mut interval = time::interval(Duration::from_secs(METRICS_INTERVAL_SECS));
+        // Publish right away so Home Assistant has data without waiting a full period
+        let result = self.update_system_metrics(true).await;
+        match &result {
+            Err(Error::Timeout(reason)) => warn!("Dropping initial system metrics: {}", reason),
+            Err(e) => error!("Failed to publish initial system metrics: {}", e),
+            Ok(()) => {}
+        }
+        self.record_update_result(result.is_ok()).await;
+
+        // Start ticking one period from now; `time::interval` would fire immediately
+        // and publish the same data twice
+        let period = Duration::from_secs(METRICS_INTERVAL_SECS);
+        let mut interval = time::interval_at(time::Instant::now() + period, period);
+        // After an update that waited on a stuck broker, continue with the
+        // regular cadence instead of catching up with a burst of updates
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
 
         loop {
-            interval.tick().await;
-            if let Err(e) = self
-                .update_system_metrics(&system_refresh_kind, &disk_refresh_kind)
-                .await
-            {
-                error!("Failed to update system metrics: {}", e);
+            // An on-demand refresh publishes even if nothing changed
+            let refresh = tokio::select! {
+                _ = interval.tick() => false,
+                _ = self.refresh_trigger.notified() => {
+                    debug!("On-demand metrics refresh");
+                    // Restart the period so the next scheduled update is a full interval away
+                    interval.reset();
+                    true
+                }
+            };
+            let result = self.update_system_metrics(refresh).await;
+            match &result {
+                Err(Error::Timeout(reason)) => warn!("Dropping system metrics sample: {}", reason),
+                Err(e) => error!("Failed to update system metrics: {}", e),
+                Ok(()) => {}
             }
+            self.record_update_result(result.is_ok()).await;
         }
     }
 
-    async fn update_system_metrics(
-        &mut self,
-        system_refresh_kind: &RefreshKind,
-        disk_refresh_kind: &DiskRefreshKind,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    async fn record_update_result(&mut self, success: bool) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.send_replace(Instant::now());
+        }
+        let Some(tracker) = self.publish_failures.as_mut() else {
+            return;
+        };
+        if success {
+            tracker.record_success(&self.client).await;
+        } else {
+            tracker.record_failure(&self.client).await;
+        }
+        if let Some(degraded) = &self.degraded {
+            let is_degraded = tracker.is_degraded();
+            degraded.send_if_modified(|current| {
+                let changed = *current != is_degraded;
+                *current = is_degraded;
+                changed
+            });
+        }
+    }
+
+    /// Collect and publish the metrics and everything evaluated alongside them.
+    /// `force` publishes the metrics even if the delta filter considers them unchanged.
+    async fn update_system_metrics(&mut self, force: bool) -> Result<()> {
         debug!("Updating system metrics");
 
-        // Use the provided RefreshKind to refresh system information
-        self.system.refresh_specifics(*system_refresh_kind);
-        // Use the provided DiskRefreshKind to refresh storage information
-        self.disks.refresh_specifics(false, *disk_refresh_kind);
+        let performance_data = self.collect();
+        self.publish(&performance_data, force).await?;
 
-        // Get disk metrics using the cached root disk
-        let disk_metrics = self.get_root_disk_metrics();
+        // Like the steps below, a failure here must not skip the rest
+        if let Some(health) = self.health.as_mut()
+            && let Err(e) = health.publish(&self.client, &performance_data).await
+        {
+            warn!("Failed to publish system health: {}", e);
+        }
 
-        // Create performance data using the refreshed system and cached disk metrics
-        let performance_data =
-            SystemPerformanceData::from_system_and_cached_disk(&self.system, disk_metrics);
+        if let Some(alerts) = self.alerts.as_mut() {
+            alerts.check(&performance_data, self.unit).await;
+        }
+
+        if let Some(psi) = &self.psi {
+            psi.publish(&self.client).await;
+        }
+
+        if !self.smart_devices.is_empty() {
+            publish_smart_health(&self.client, &self.smart_devices).await;
+        }
+
+        if !self.availability_probes.is_empty() {
+            publish_probe_availability(
+                &self.client,
+                &self.availability_probes,
+                &self.availability_payloads,
+            )
+            .await;
+        }
+
+        if let Some(notification_availability) = self.notification_availability.as_mut() {
+            notification_availability.publish(&self.client).await;
+        }
+
+        Ok(())
+    }
+
+    /// Publish already collected metrics to the performance state topic
+    async fn publish(
+        &mut self,
+        performance_data: &SystemPerformanceData,
+        force: bool,
+    ) -> Result<()> {
+        let mut values = performance_data.to_json(self.unit)?;
+        if let Some(fields) = values.as_object_mut() {
+            for metric in &self.disabled_metrics {
+                fields.remove(metric);
+            }
+        }
+
+        if !force
+            && let Some(filter) = &self.delta_filter
+            && !filter.should_publish(&values)
+        {
+            debug!("System metrics within metrics_min_delta, skipping publish");
+            return Ok(());
+        }
+        let published_values = self.delta_filter.is_some().then(|| values.clone());
 
         info!(
-            "Publishing system performance - CPU: {:.2}%, Freq: {:?} GHz, Memory: {:.2}/{:.2} GB ({:.1}% free), Disk: {:.2}/{:.2} GB ({:.1}% free)",
+            "Publishing system performance - CPU: {:.2}%, Freq: {:?} GHz, Memory: {:?}/{:?} GB ({:?}% free), Disk: {:?}/{:?} GB ({:?}% free)",
             performance_data.cpu_load,
             performance_data.cpu_frequency,
             performance_data.memory_free,
@@ -277,39 +943,186 @@ impl SystemMonitor {
             performance_data.disk_free_percentage
         );
 
-        // Publish to single topic
-        let performance_json = serde_json::to_string(&performance_data)?;
+        self.publish_metric_availability(&values).await;
 
-        self.client
-            .publish(&self.sensor_topic, QoS::AtMostOnce, false, performance_json)
-            .await?;
+        let annotated = self.payload_format == MetricsPayloadFormat::Annotated;
+        match self.topic_mode {
+            MetricsTopicMode::Shared => {
+                if annotated && let Some(fields) = values.as_object_mut() {
+                    for metric in enabled_metrics(&self.disabled_metrics) {
+                        if let Some(value) = fields.get_mut(metric.json_field) {
+                            *value = annotate_value(metric, self.unit, value);
+                        }
+                    }
+                }
+                let performance_json = values.to_string();
 
+                self.client
+                    .publish(&self.sensor_topic, QoS::AtMostOnce, false, performance_json)
+                    .await?;
+            }
+            MetricsTopicMode::PerMetric => {
+                for metric in enabled_metrics(&self.disabled_metrics) {
+                    // Unavailable metrics (e.g. CPU frequency) are serialized as null
+                    let Some(value) = values.get(metric.json_field).filter(|v| !v.is_null()) else {
+                        continue;
+                    };
+                    let topic =
+                        Self::create_topic(&self.sensor_topic_base, metric.json_field, "state");
+                    let payload = if annotated {
+                        annotate_value(metric, self.unit, value).to_string()
+                    } else {
+                        value.to_string()
+                    };
+                    self.client
+                        .publish(&topic, QoS::AtMostOnce, false, payload)
+                        .await?;
+                }
+            }
+        }
+
+        if let (Some(filter), Some(values)) = (self.delta_filter.as_mut(), published_values) {
+            filter.record_published(values);
+        }
         Ok(())
     }
+
+    /// Mark metrics that could not be collected (serialized as null) offline
+    /// and the others online. Only changes are published, retained.
+    async fn publish_metric_availability(&mut self, values: &serde_json::Value) {
+        for metric in enabled_metrics(&self.disabled_metrics) {
+            let available = values.get(metric.json_field).is_some_and(|v| !v.is_null());
+            if self.metric_availability.get(metric.json_field) == Some(&available) {
+                continue;
+            }
+
+            let payload = self.availability_payloads.payload(available);
+            debug!("Metric '{}' availability: {}", metric.json_field, payload);
+
+            let topic = metric_availability_topic(&self.sensor_topic_base, metric);
+            match self
+                .client
+                .publish(&topic, QoS::AtLeastOnce, true, payload)
+                .await
+            {
+                Ok(()) => {
+                    self.metric_availability
+                        .insert(metric.json_field, available);
+                }
+                Err(e) => warn!(
+                    "Failed to publish availability of metric '{}': {}",
+                    metric.json_field, e
+                ),
+            }
+        }
+    }
+}
+
+/// Creates the built-in button that triggers an immediate metrics refresh
+/// and returns its command topic for subscription
+pub async fn create_refresh_button_component_and_setup(
+    config: &Config,
+) -> Result<((String, HomeAssistantComponent), String)> {
+    let refresh_id = format!("{}_refresh", config.hostname);
+    let refresh_topic = format!("{}/refresh/set", config.button_topic);
+
+    let component = HomeAssistantComponent::button(
+        "Refresh Metrics".to_string(),
+        refresh_id.clone(),
+        refresh_topic.clone(),
+    );
+
+    Ok(((refresh_id, component), refresh_topic))
 }
 
 /// Creates system monitoring sensor components
 pub fn create_system_sensor_components(config: &Config) -> Vec<(String, HomeAssistantComponent)> {
     let mut components = Vec::new();
-    let state_topic =
+    let shared_topic =
         SystemMonitor::create_topic(&config.sensor_topic_base, "system_performance", "state");
 
-    for metric in SYSTEM_METRICS {
-        let component_id = format!(
-            "{}_{}",
-            config.hostname,
-            metric.json_field.replace(' ', "_").to_lowercase()
-        );
+    for metric in enabled_metrics(&config.disabled_metrics) {
+        let component_id = metric_component_id(&config.hostname, metric);
+        let annotated = config.metrics_payload_format == MetricsPayloadFormat::Annotated;
+        let (state_topic, value_template) = match (config.metrics_topic_mode, annotated) {
+            (MetricsTopicMode::Shared, false) => (
+                shared_topic.clone(),
+                Some(format!("{{{{ value_json.{} }}}}", metric.json_field)),
+            ),
+            (MetricsTopicMode::Shared, true) => (
+                shared_topic.clone(),
+                Some(format!("{{{{ value_json.{}.value }}}}", metric.json_field)),
+            ),
+            (MetricsTopicMode::PerMetric, annotated) => (
+                SystemMonitor::create_topic(&config.sensor_topic_base, metric.json_field, "state"),
+                annotated.then(|| "{{ value_json.value }}".to_string()),
+            ),
+        };
         let component = HomeAssistantComponent::sensor(
             metric.name.to_string(),
             component_id.clone(),
-            state_topic.clone(),
+            state_topic,
             metric.device_class.map(|s| s.to_string()),
-            metric.unit.map(|s| s.to_string()),
-            format!("{{{{ value_json.{} }}}}", metric.json_field),
+            metric_unit(metric, config.metrics_unit).map(|s| s.to_string()),
+            value_template,
+        )
+        .with_suggested_display_precision(metric_display_precision(metric, config.metrics_unit))
+        .with_enabled_by_default(
+            config
+                .metrics_enabled_by_default
+                .get(metric.json_field)
+                .copied()
+                .unwrap_or(metric.enabled_by_default),
         );
         components.push((component_id, component));
     }
 
     components
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_terabyte_disk_sizes_keep_their_precision() {
+        const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+        assert_eq!(
+            SystemPerformanceData::size_metrics_gb(16 * TIB, 4 * TIB),
+            Some((16384.0, 4096.0, 25.0))
+        );
+        // 12 TB as sold, with 1 byte free
+        assert_eq!(
+            SystemPerformanceData::size_metrics_gb(12_000_000_000_000, 1),
+            Some((11175.87, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn zero_total_memory_leaves_the_sizes_unknown() {
+        assert_eq!(SystemPerformanceData::size_metrics_gb(0, 0), None);
+
+        let mut data = SystemPerformanceData {
+            cpu_load: 0.0,
+            cpu_frequency: None,
+            memory_total: Some(1.0),
+            memory_free: Some(1.0),
+            memory_free_percentage: Some(100.0),
+            disk_total: None,
+            disk_free: None,
+            disk_free_percentage: None,
+            sizes_bytes: SizeBytes::default(),
+        };
+        data.set_memory(0, 1024);
+        assert_eq!(data.memory_total, None);
+        assert_eq!(data.memory_free, None);
+        assert_eq!(data.memory_free_percentage, None);
+        assert_eq!(data.sizes_bytes.memory_total, None);
+        assert_eq!(data.sizes_bytes.memory_free, None);
+        for unit in [MetricsUnit::Gb, MetricsUnit::Bytes] {
+            let json = data.to_json(unit).unwrap();
+            assert!(json["memory_total"].is_null());
+            assert!(json["memory_free_percentage"].is_null());
+        }
+    }
+}
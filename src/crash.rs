@@ -0,0 +1,149 @@
+// Crash reporting - a panic hook that publishes a "Crashed" status over a
+// separate, blocking MQTT connection before the process dies, since the async
+// client cannot be driven from within a panic
+
+use rumqttc::{Client, Event, MqttOptions, Outgoing, QoS};
+use std::panic::{self, PanicHookInfo};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+use crate::dbus::{AvailabilityPayloads, DaemonState, status_payload, status_state_topic};
+use crate::error::Result;
+use crate::ha_mqtt::client::KEEP_ALIVE_SECS;
+use crate::ha_mqtt::tls;
+use crate::utils::Config;
+
+/// How long a panicking process waits for the crash status to be published
+const CRASH_PUBLISH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Reporter used by the panic hook, replaced when the config is reloaded
+static CRASH_REPORTER: Mutex<Option<CrashReporter>> = Mutex::new(None);
+
+/// Set by the first panic, so a panic while reporting does not report again
+static CRASH_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Everything needed to publish the crash status without the running client
+#[derive(Clone, Debug)]
+pub struct CrashReporter {
+    options: MqttOptions,
+    bind_interface: Option<String>,
+    status_topic: String,
+    status_payload: String,
+    availability_topic: String,
+    not_available_payload: String,
+}
+
+impl CrashReporter {
+    /// Prepare the connection options and payloads up front, so nothing
+    /// that can fail is left for the panic hook
+    pub fn from_config(config: &Config) -> Result<Self> {
+        // A client id of its own, so the broker does not drop the daemon's
+        // connection in favour of this one while it is still alive
+        let mut options = MqttOptions::new(
+            format!("{}-crash", config.hostname),
+            &config.mqtt_url,
+            config.broker_port,
+        );
+        options.set_credentials(&config.username, &config.password);
+        options.set_keep_alive(Duration::from_secs(KEEP_ALIVE_SECS));
+        if let Some(transport) = tls::transport(config)? {
+            options.set_transport(transport);
+        }
+
+        Ok(Self {
+            options,
+            bind_interface: config.bind_interface.clone(),
+            status_topic: status_state_topic(&config.hostname),
+            status_payload: status_payload(&config.status_labels, DaemonState::Crashed)?,
+            availability_topic: config.availability_topic.clone(),
+            not_available_payload: AvailabilityPayloads::from_config(config)
+                .payload(false)
+                .to_string(),
+        })
+    }
+
+    /// Connect, publish the retained crash status and availability, and
+    /// disconnect again, blocking until done or the connection fails
+    fn publish(self) -> Result<()> {
+        let (client, mut connection) = Client::new(self.options, 10);
+        if let Some(interface) = &self.bind_interface {
+            let mut network_options = connection.eventloop.network_options();
+            network_options.set_bind_device(interface);
+            connection.eventloop.set_network_options(network_options);
+        }
+
+        client.publish(
+            &self.status_topic,
+            QoS::AtLeastOnce,
+            true,
+            self.status_payload,
+        )?;
+        client.publish(
+            &self.availability_topic,
+            QoS::AtLeastOnce,
+            true,
+            self.not_available_payload,
+        )?;
+        client.disconnect()?;
+
+        for event in connection.iter() {
+            if let Event::Outgoing(Outgoing::Disconnect) = event? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Use `config` for the crash status published by the panic hook
+pub fn set_crash_reporter(config: &Config) {
+    let reporter = match CrashReporter::from_config(config) {
+        Ok(reporter) => Some(reporter),
+        Err(e) => {
+            warn!("Crash status reporting is unavailable: {}", e);
+            None
+        }
+    };
+    if let Ok(mut current) = CRASH_REPORTER.lock() {
+        *current = reporter;
+    }
+}
+
+/// Install a panic hook that logs the panic and publishes the crash status,
+/// then hands over to the default hook
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        report_panic(info);
+        default_hook(info);
+    }));
+}
+
+fn report_panic(info: &PanicHookInfo<'_>) {
+    error!("Daemon panicked: {}", info);
+    if CRASH_REPORTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let Some(reporter) = CRASH_REPORTER
+        .lock()
+        .ok()
+        .and_then(|reporter| reporter.clone())
+    else {
+        return;
+    };
+
+    // The panicking thread may be a runtime worker, where the blocking
+    // client cannot start its own runtime, so publish from a fresh thread
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = done_tx.send(reporter.publish());
+    });
+    match done_rx.recv_timeout(CRASH_PUBLISH_TIMEOUT) {
+        Ok(Ok(())) => debug!("Published crash status"),
+        Ok(Err(e)) => error!("Failed to publish crash status: {}", e),
+        Err(_) => error!("Timeout publishing crash status"),
+    }
+}
@@ -3,6 +3,7 @@
 use futures::StreamExt;
 use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
+use zbus::proxy::SignalStream;
 use zbus::{Connection, Proxy, Result};
 
 use super::power_management::PowerEvent;
@@ -13,6 +14,9 @@ const DBUS_OBJECT_PATH: &str = "/org/freedesktop/login1";
 const DBUS_INTERFACE_NAME: &str = "org.freedesktop.login1.Manager";
 const APP_NAME: &str = "mqtt-agent";
 const INHIBIT_MODE: &str = "delay";
+const DEFAULT_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const MONITOR_RECONNECT_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const MONITOR_RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(300);
 
 /// Type of inhibitor to acquire from logind
 #[derive(Debug, Clone, Copy)]
@@ -103,6 +107,9 @@ pub struct PowerManager {
 
     /// Active shutdown inhibitor lock, if one has been created
     shutdown_inhibitor: Option<Inhibitor>,
+
+    /// Maximum time to wait for the system D-Bus connection
+    connect_timeout: std::time::Duration,
 }
 
 impl PowerManager {
@@ -118,6 +125,7 @@ impl PowerManager {
             connection: None,
             suspend_inhibitor: None,
             shutdown_inhibitor: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
         }
     }
 
@@ -131,9 +139,29 @@ impl PowerManager {
             connection: None,
             suspend_inhibitor: None,
             shutdown_inhibitor: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
         }
     }
 
+    /// Set how long to wait for the system D-Bus before giving up
+    pub fn set_connect_timeout(&mut self, connect_timeout: std::time::Duration) {
+        self.connect_timeout = connect_timeout;
+    }
+
+    /// Whether a system D-Bus connection has been established
+    pub fn is_dbus_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Create a manager sharing this one's event channel, D-Bus connection
+    /// and connect timeout, but none of its inhibitors
+    pub(crate) fn share(&self) -> Self {
+        let mut manager = Self::new_with_sender(self.clone_sender());
+        manager.connection = self.connection.clone();
+        manager.connect_timeout = self.connect_timeout;
+        manager
+    }
+
     /// Connect to the system D-Bus
     ///
     /// This must be called before creating inhibitors or starting the monitor.
@@ -147,9 +175,16 @@ impl PowerManager {
     /// Returns a reference to the connection if successful
     async fn ensure_connection(&mut self) -> Result<&Connection> {
         if self.connection.is_none() {
-            // Try to connect to the system D-Bus
-            let conn = Connection::system()
+            // Try to connect to the system D-Bus, bounded so an unreachable
+            // bus cannot block startup
+            let conn = tokio::time::timeout(self.connect_timeout, Connection::system())
                 .await
+                .map_err(|_| {
+                    zbus::Error::Failure(format!(
+                        "Timed out after {}ms connecting to D-Bus",
+                        self.connect_timeout.as_millis()
+                    ))
+                })?
                 .map_err(|e| zbus::Error::Failure(format!("Failed to connect to D-Bus: {}", e)))?;
 
             info!("Successfully connected to system D-Bus");
@@ -223,75 +258,98 @@ impl PowerManager {
         self.shutdown_inhibitor.take();
     }
 
-    /// Run the power event monitor
-    ///
-    /// This method sets up a listener for power events and broadcasts them.
-    /// It runs indefinitely and should be called in a separate task.
-    pub(crate) async fn run_monitor(&mut self) -> Result<()> {
-        // Use the ensure_connection helper to get or establish a connection
-        let connection = match self.ensure_connection().await {
-            Ok(conn) => conn.clone(),
-            Err(e) => {
-                return self.handle_dbus_error(e, "connect to system D-Bus").await;
-            }
-        };
+    /// Connect if needed and subscribe to logind's PrepareForSleep signal
+    async fn subscribe_to_sleep_signal(&mut self) -> Result<SignalStream<'static>> {
+        let connection = self.ensure_connection().await?.clone();
 
         // Create a proxy for the login1 manager interface
-        let proxy = match Proxy::new(
+        let proxy = Proxy::new(
             &connection,
             DBUS_SERVICE_NAME,
             DBUS_OBJECT_PATH,
             DBUS_INTERFACE_NAME,
         )
         .await
-        {
-            Ok(p) => {
-                debug!("Successfully created login1 manager proxy");
-                p
-            }
-            Err(e) => {
-                return self
-                    .handle_dbus_error(e, "create login1 manager proxy")
-                    .await;
-            }
-        };
-
-        // Subscribe to the PrepareForSleep signal
-        let sender = self.event_sender.clone();
-        let mut stream = match proxy.receive_signal("PrepareForSleep").await {
-            Ok(s) => {
-                debug!("Successfully subscribed to PrepareForSleep signals");
-                s
-            }
-            Err(e) => {
-                return self
-                    .handle_dbus_error(e, "subscribe to PrepareForSleep signals")
-                    .await;
-            }
-        };
-
-        info!("Power monitor started, listening for suspend/resume events");
+        .map_err(|e| {
+            zbus::Error::Failure(format!("Failed to create login1 manager proxy: {}", e))
+        })?;
+        debug!("Successfully created login1 manager proxy");
+
+        let stream = proxy.receive_signal("PrepareForSleep").await.map_err(|e| {
+            zbus::Error::Failure(format!(
+                "Failed to subscribe to PrepareForSleep signals: {}",
+                e
+            ))
+        })?;
+        debug!("Successfully subscribed to PrepareForSleep signals");
+        Ok(stream)
+    }
 
+    /// Broadcast power events until the signal stream ends
+    async fn forward_power_events(&self, stream: &mut SignalStream<'static>) {
         while let Some(msg) = stream.next().await {
             // Extract the boolean value from the signal and send appropriate event
             match msg.body().deserialize::<bool>() {
                 Ok(true) => {
                     info!("System is about to suspend");
-                    if let Err(e) = sender.send(PowerEvent::Suspending) {
+                    if let Err(e) = self.event_sender.send(PowerEvent::Suspending) {
                         error!("Failed to broadcast suspending event: {}", e);
                     }
                 }
                 Ok(false) => {
                     info!("System is resuming from suspend");
-                    if let Err(e) = sender.send(PowerEvent::Resuming) {
+                    if let Err(e) = self.event_sender.send(PowerEvent::Resuming) {
                         error!("Failed to broadcast resuming event: {}", e);
                     }
                 }
                 Err(e) => error!("Failed to parse PrepareForSleep signal: {}", e),
             }
         }
+    }
 
-        Ok(())
+    /// Reconnect to D-Bus and resubscribe, retrying with exponential backoff
+    async fn resubscribe_with_backoff(&mut self) -> SignalStream<'static> {
+        let mut delay = MONITOR_RECONNECT_INITIAL_DELAY;
+        loop {
+            tokio::time::sleep(delay).await;
+            match self.subscribe_to_sleep_signal().await {
+                Ok(stream) => return stream,
+                Err(e) => {
+                    delay = (delay * 2).min(MONITOR_RECONNECT_MAX_DELAY);
+                    warn!(
+                        "Power monitor could not reconnect to D-Bus: {}. Retrying in {}s",
+                        e,
+                        delay.as_secs()
+                    );
+                    self.connection = None;
+                }
+            }
+        }
+    }
+
+    /// Run the power event monitor
+    ///
+    /// This method sets up a listener for power events and broadcasts them.
+    /// It runs indefinitely and should be called in a separate task. If the
+    /// D-Bus connection drops later on, it reconnects and resubscribes.
+    pub(crate) async fn run_monitor(&mut self) -> Result<()> {
+        // Failing on the first attempt usually means there is no usable system
+        // D-Bus at all, so power monitoring is disabled rather than retried
+        let mut stream = match self.subscribe_to_sleep_signal().await {
+            Ok(stream) => stream,
+            Err(e) => return self.handle_dbus_error(e, "start power monitoring").await,
+        };
+
+        info!("Power monitor started, listening for suspend/resume events");
+
+        loop {
+            self.forward_power_events(&mut stream).await;
+
+            warn!("PrepareForSleep signal stream ended, reconnecting to D-Bus");
+            self.connection = None;
+            stream = self.resubscribe_with_backoff().await;
+            info!("Power monitor reconnected, listening for suspend/resume events");
+        }
     }
 
     /// Get a clone of the event sender
@@ -310,7 +368,7 @@ impl PowerManager {
     /// * `&mut broadcast::Receiver<PowerEvent>` - Mutable reference to the event receiver
     ///
     /// # Examples
-    /// ```
+    /// ```ignore
     /// let receiver = power_manager.get_receiver();
     /// ```
     pub fn get_receiver(&mut self) -> &mut broadcast::Receiver<PowerEvent> {
@@ -7,8 +7,14 @@ pub mod status;
 
 // Re-export public types and functions
 pub use inhibitor::PowerManager;
-pub use notifications::send_desktop_notification;
+pub use notifications::{
+    NotificationOptions, NotificationRetry, send_desktop_notification,
+    send_desktop_notification_retrying, send_desktop_notification_with, test_notification_service,
+};
 pub use power_management::{
     PowerEvent, PowerEventHandler, handle_power_events, setup_power_monitoring,
 };
-pub use status::{StatusManager, create_status_component};
+pub use status::{
+    AvailabilityPayloads, DaemonState, StatusManager, create_status_component, status_payload,
+    status_state_topic,
+};
@@ -1,39 +1,180 @@
+use crate::error::Result;
+use crate::utils::Config;
 use std::collections::HashMap;
+use std::time::Duration;
 use tracing::{debug, error, info, warn};
-use zbus::{Connection, zvariant::Value};
+use zbus::{Connection, connection, zvariant::Value};
 
-/// Send a desktop notification via D-Bus using low-level call_method
-pub async fn send_desktop_notification(
-    summary: &str,
-    message: &str,
-    urgency: u8,
-) -> Result<(), Box<dyn std::error::Error>> {
-    debug!("Sending desktop notification: {} - {}", summary, message);
+/// Optional notification settings that replace the built-in defaults
+#[derive(Debug, Clone, Default)]
+pub struct NotificationOptions {
+    /// Application name shown by the notification daemon
+    pub app_name: Option<String>,
+    /// Freedesktop icon name or path, instead of the urgency-based icon
+    pub icon: Option<String>,
+    /// Image file passed in the `image-path` hint
+    pub image: Option<String>,
+    /// Bus tried before the daemon's own session bus
+    pub bus_address: Option<String>,
+}
+
+impl NotificationOptions {
+    /// Options with the notification bus configured in `config`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            bus_address: config.notification_bus_address(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Retries for notifications the notification service did not accept
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NotificationRetry {
+    /// Attempts after the first one
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub delay: Duration,
+}
+
+impl NotificationRetry {
+    /// Retries as configured by `notification_retry_attempts` and `notification_retry_delay_ms`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            attempts: config.notification_retry_attempts,
+            delay: Duration::from_millis(config.notification_retry_delay_ms),
+        }
+    }
+}
+
+/// Upper bound for checking the notification service, which may be started
+/// on demand by D-Bus activation
+const SERVICE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connect to the configured notification bus, falling back to the session
+/// and then the system bus
+async fn connect_notification_bus(bus_address: Option<&str>) -> Result<Connection> {
+    if let Some(address) = bus_address {
+        let connection = match connection::Builder::address(address) {
+            Ok(builder) => builder.build().await,
+            Err(e) => Err(e),
+        };
+        match connection {
+            Ok(conn) => {
+                debug!("Connected to notification bus {}", address);
+                return Ok(conn);
+            }
+            Err(e) => warn!(
+                "Failed to connect to notification bus {}: {}, falling back to session D-Bus",
+                address, e
+            ),
+        }
+    }
 
     // Try to connect to session D-Bus first
-    let connection = match Connection::session().await {
+    match Connection::session().await {
         Ok(conn) => {
             debug!("Connected to session D-Bus for notifications");
-            conn
+            Ok(conn)
         }
         Err(e) => {
             warn!("Failed to connect to session D-Bus: {}", e);
             // Fall back to system D-Bus if session is not available
             debug!("Attempting to connect to system D-Bus as fallback");
-            Connection::system().await.map_err(|sys_err| {
-                format!("Failed to connect to both session and system D-Bus. Session error: {}, System error: {}", e, sys_err)
-            })?
+            Ok(Connection::system().await.map_err(|sys_err| {
+                zbus::Error::Failure(format!("Failed to connect to both session and system D-Bus. Session error: {}, System error: {}", e, sys_err))
+            })?)
         }
+    }
+}
+
+/// Whether a notification daemon answers on the notification bus, e.g. false
+/// at the login screen without a desktop session
+pub async fn test_notification_service(options: &NotificationOptions) -> bool {
+    let check = async {
+        let connection = connect_notification_bus(options.bus_address.as_deref()).await?;
+        connection
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "GetServerInformation",
+                &(),
+            )
+            .await?;
+        Ok::<_, crate::error::Error>(())
     };
 
+    match tokio::time::timeout(SERVICE_CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            debug!("Notification service unavailable: {}", e);
+            false
+        }
+        Err(_) => {
+            debug!(
+                "Notification service did not answer within {}s",
+                SERVICE_CHECK_TIMEOUT.as_secs()
+            );
+            false
+        }
+    }
+}
+
+/// Send a desktop notification via D-Bus using low-level call_method
+pub async fn send_desktop_notification(summary: &str, message: &str, urgency: u8) -> Result<()> {
+    send_desktop_notification_with(summary, message, urgency, &NotificationOptions::default()).await
+}
+
+/// Send a desktop notification, retrying with backoff if the notification
+/// service is briefly unavailable, e.g. during session startup
+pub async fn send_desktop_notification_retrying(
+    summary: &str,
+    message: &str,
+    urgency: u8,
+    options: &NotificationOptions,
+    retry: NotificationRetry,
+) -> Result<()> {
+    let mut delay = retry.delay;
+    let mut attempt = 0;
+    loop {
+        match send_desktop_notification_with(summary, message, urgency, options).await {
+            Ok(()) => return Ok(()),
+            Err(_) if attempt < retry.attempts => {
+                attempt += 1;
+                warn!(
+                    "Retrying notification '{}' in {}ms (retry {}/{})",
+                    summary,
+                    delay.as_millis(),
+                    attempt,
+                    retry.attempts
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Send a desktop notification with custom app name or icon
+pub async fn send_desktop_notification_with(
+    summary: &str,
+    message: &str,
+    urgency: u8,
+    options: &NotificationOptions,
+) -> Result<()> {
+    debug!("Sending desktop notification: {} - {}", summary, message);
+
+    let connection = connect_notification_bus(options.bus_address.as_deref()).await?;
+
     // Notification parameters
-    let app_name = "MQTT Agent";
+    let app_name = options.app_name.as_deref().unwrap_or("MQTT Agent");
     let replaces_id: u32 = 0;
-    let app_icon = match urgency {
-        0 => "dialog-information", // Low urgency
-        1 => "dialog-information", // Normal urgency
-        2 => "dialog-warning",     // High/Critical urgency
-        _ => "dialog-information",
+    let app_icon = match (options.icon.as_deref(), urgency) {
+        (Some(icon), _) => icon,
+        (None, 2) => "dialog-warning", // High/Critical urgency
+        (None, _) => "dialog-information",
     };
     let timeout: i32 = match urgency {
         0 => 5000,  // Low urgency: 5 seconds
@@ -48,6 +189,10 @@ pub async fn send_desktop_notification(
     let mut hints = HashMap::new();
     hints.insert("urgency", &urgency_value);
     hints.insert("category", &category_value);
+    let image_value = options.image.as_deref().map(Value::from);
+    if let Some(image_value) = &image_value {
+        hints.insert("image-path", image_value);
+    }
 
     // Use low-level call_method directly on the connection
     match connection
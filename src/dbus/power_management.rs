@@ -1,14 +1,23 @@
 // Power management module - handles power events and system state management
 
-use rumqttc::AsyncClient;
+use std::time::Duration;
 use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
 use super::inhibitor::PowerManager;
-use crate::Config;
-use crate::dbus::status::StatusManager;
-use crate::ha_mqtt::TopicHandlers;
+use crate::dbus::status::{DaemonState, StatusManager};
+use crate::error::{Error, Result};
+use crate::ha_mqtt::session::spawn_resubscribe;
+use crate::ha_mqtt::{MqttClient, MqttEventLoop, TopicHandlers, spawn_system_monitor};
 use crate::shutdown::{ShutdownScenario, perform_graceful_mqtt_shutdown};
+use crate::utils::Config;
+use crate::utils::config::MAX_SUSPEND_GRACE_MS;
+
+/// How long to wait for the broker to accept a reconnection after resume
+const RESUME_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause between reconnection attempts while probing the broker after resume
+const RESUME_PROBE_RETRY_DELAY: Duration = Duration::from_millis(250);
 
 /// Power event types that can be received from the system
 #[derive(Debug, Clone)]
@@ -19,8 +28,12 @@ pub enum PowerEvent {
 
 /// Setup function to initialize power monitoring and create inhibitors
 /// Returns a PowerManager instance and starts the monitoring task
-pub async fn setup_power_monitoring() -> (PowerManager, tokio::task::JoinHandle<()>) {
+pub async fn setup_power_monitoring(
+    config: &Config,
+) -> (PowerManager, tokio::task::JoinHandle<()>) {
+    let connect_timeout = Duration::from_millis(config.dbus_timeout_ms);
     let mut power_manager = PowerManager::new();
+    power_manager.set_connect_timeout(connect_timeout);
 
     // Establish D-Bus connection once for both monitoring and inhibitors
     if let Err(e) = power_manager.connect_dbus().await {
@@ -56,8 +69,9 @@ pub async fn setup_power_monitoring() -> (PowerManager, tokio::task::JoinHandle<
         info!("Created shutdown inhibitor (delay mode with system default timeout)");
     }
 
-    // Get the sender for creating a new PowerManager for the main loop
-    let event_sender = power_manager.clone_sender();
+    // Create a new PowerManager for the main loop, sharing the event channel
+    // and D-Bus connection
+    let main_power_manager = power_manager.share();
 
     // Start power monitoring using the same PowerManager instance
     let monitor_handle = tokio::spawn(async move {
@@ -67,9 +81,6 @@ pub async fn setup_power_monitoring() -> (PowerManager, tokio::task::JoinHandle<
         }
     });
 
-    // Create a new PowerManager for the main loop (with shared sender)
-    let main_power_manager = PowerManager::new_with_sender(event_sender);
-
     (main_power_manager, monitor_handle)
 }
 
@@ -92,11 +103,11 @@ pub async fn handle_power_events(power_manager: &mut PowerManager) -> Option<Pow
 /// Handler for power events that encapsulates all power management actions
 pub struct PowerEventHandler<'a> {
     power_manager: &'a mut PowerManager,
-    client: &'a mut AsyncClient,
-    eventloop: &'a mut rumqttc::EventLoop,
+    client: &'a mut MqttClient,
+    eventloop: &'a mut MqttEventLoop,
     topic_handlers: &'a mut TopicHandlers,
     status_manager: &'a mut StatusManager,
-    system_monitor_handle: &'a mut tokio::task::JoinHandle<()>,
+    system_monitor_handle: &'a mut Option<tokio::task::JoinHandle<()>>,
     config: &'a Config,
 }
 
@@ -104,11 +115,11 @@ impl<'a> PowerEventHandler<'a> {
     /// Create a new power event handler with all required components
     pub fn new(
         power_manager: &'a mut PowerManager,
-        client: &'a mut AsyncClient,
-        eventloop: &'a mut rumqttc::EventLoop,
+        client: &'a mut MqttClient,
+        eventloop: &'a mut MqttEventLoop,
         topic_handlers: &'a mut TopicHandlers,
         status_manager: &'a mut StatusManager,
-        system_monitor_handle: &'a mut tokio::task::JoinHandle<()>,
+        system_monitor_handle: &'a mut Option<tokio::task::JoinHandle<()>>,
         config: &'a Config,
     ) -> Self {
         Self {
@@ -122,6 +133,13 @@ impl<'a> PowerEventHandler<'a> {
         }
     }
 
+    /// Stop the system monitor task, if monitoring is enabled
+    fn stop_system_monitor(&mut self) {
+        if let Some(handle) = self.system_monitor_handle.as_ref() {
+            handle.abort();
+        }
+    }
+
     /// Handle a power event by dispatching to the appropriate handler method
     pub async fn handle_event(&mut self, event: PowerEvent) {
         match event {
@@ -133,13 +151,27 @@ impl<'a> PowerEventHandler<'a> {
 
     /// Handle system suspend by gracefully shutting down services
     async fn handle_suspend(&mut self) {
+        if self.config.suspend_grace_ms > 0 {
+            // Still holding the inhibitor, so the system waits for us
+            let grace =
+                Duration::from_millis(self.config.suspend_grace_ms.min(MAX_SUSPEND_GRACE_MS));
+            info!(
+                "System is about to suspend, deferring shutdown actions for a {}ms grace period",
+                grace.as_millis()
+            );
+            if self.wait_for_resume(grace).await {
+                info!("System resumed within the grace period, keeping the existing connection");
+                return;
+            }
+        }
+
         info!("System is about to suspend, performing shutdown actions...");
 
         // We already have an inhibitor from startup, so we can proceed with shutdown actions
         // The existing inhibitor gives us up to 2 seconds to complete our work
 
         // Stop system monitoring
-        self.system_monitor_handle.abort();
+        self.stop_system_monitor();
         debug!("Stopped system monitoring");
 
         // Use the general MQTT shutdown function with proper event queue draining
@@ -162,21 +194,41 @@ impl<'a> PowerEventHandler<'a> {
         debug!("Pre-suspend actions completed, released inhibitor to allow system suspend");
     }
 
+    /// Wait up to `grace` for the suspend to be cancelled by a resume.
+    /// Returns true if the resume arrived first.
+    async fn wait_for_resume(&mut self, grace: Duration) -> bool {
+        let resumed = async {
+            loop {
+                match handle_power_events(self.power_manager).await {
+                    Some(PowerEvent::Resuming) => return true,
+                    Some(PowerEvent::Suspending) => {}
+                    None => return false,
+                }
+            }
+        };
+        tokio::select! {
+            resumed = resumed => resumed,
+            _ = tokio::time::sleep(grace) => false,
+        }
+    }
+
     /// Helper method for retry logic with exponential backoff
     async fn retry_dbus_operation<T, E>(
         &mut self,
         operation_name: &str,
         operation: impl Fn(
             &mut PowerManager,
-        )
-            -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, E>> + '_>>,
+        ) -> std::pin::Pin<
+            Box<dyn std::future::Future<Output = std::result::Result<T, E>> + '_>,
+        >,
         max_retries: u32,
-    ) -> Result<T, E>
+    ) -> std::result::Result<T, E>
     where
         E: std::fmt::Display,
     {
+        let max_retries = max_retries.max(1);
         let mut attempt = 0;
-        let mut delay_ms = 500; // Start with 500ms delay
+        let mut delay_ms = self.config.dbus_retry_delay_ms;
 
         loop {
             attempt += 1;
@@ -208,11 +260,84 @@ impl<'a> PowerEventHandler<'a> {
         }
     }
 
+    /// Probe the broker by driving the existing event loop until it reconnects
+    async fn probe_mqtt_connection(&mut self) -> Result<()> {
+        // The first polls after a suspend usually fail on the dead socket,
+        // so keep reconnecting until the broker acknowledges or time runs out
+        let probe = async {
+            loop {
+                match self.eventloop.poll().await {
+                    Ok(event) if event.is_conn_ack() => return Ok::<(), Error>(()),
+                    Ok(_) => {}
+                    Err(e) => {
+                        debug!("Reconnection attempt after resume failed: {}", e);
+                        tokio::time::sleep(RESUME_PROBE_RETRY_DELAY).await;
+                    }
+                }
+            }
+        };
+
+        tokio::time::timeout(RESUME_PROBE_TIMEOUT, probe)
+            .await
+            .map_err(|_| Error::Timeout("waiting for broker reconnection".to_string()))?
+    }
+
+    /// Recover after resume by reusing the existing client and event loop.
+    /// Discovery is retained by the broker, so only subscriptions, status and
+    /// the system monitor need to be restored.
+    async fn recover_existing_connection(&mut self) -> Result<()> {
+        self.probe_mqtt_connection().await?;
+
+        self.status_manager
+            .publish_state(DaemonState::Connected)
+            .await?;
+
+        // More subscriptions than the request channel holds would block until
+        // the main loop polls the event loop again, so queue them in the background
+        spawn_resubscribe(self.client, self.topic_handlers.get_subscription_topics());
+
+        self.stop_system_monitor();
+        *self.system_monitor_handle =
+            spawn_system_monitor(self.config, self.client, self.topic_handlers);
+
+        Ok(())
+    }
+
     /// Handle system resume by re-establishing connections and services
     async fn handle_resume(&mut self) {
         info!("System resumed from suspend, re-establishing connections...");
 
-        // Re-initialize MQTT connection
+        match self.recover_existing_connection().await {
+            Ok(()) => {
+                info!("MQTT connection recovered without full re-initialization");
+            }
+            Err(e) => {
+                warn!(
+                    "Lightweight MQTT recovery failed: {}. Falling back to full re-initialization",
+                    e
+                );
+                self.reinitialize_mqtt_connection().await;
+            }
+        }
+
+        self.restore_dbus_inhibitor().await;
+
+        // In the background, as resubscribing or the startup task may have
+        // filled the request channel while the event loop is not polled here
+        let status_manager = self.status_manager.clone();
+        let dbus_connected = self.power_manager.is_dbus_connected();
+        tokio::spawn(async move {
+            if let Err(e) = status_manager
+                .publish_dbus_availability(dbus_connected)
+                .await
+            {
+                warn!("Failed to publish D-Bus availability after resume: {}", e);
+            }
+        });
+    }
+
+    /// Tear down and recreate the MQTT connection, including discovery
+    async fn reinitialize_mqtt_connection(&mut self) {
         info!("Re-initializing MQTT connection after resume");
         match crate::ha_mqtt::initialize_mqtt_connection(self.config).await {
             Ok((
@@ -221,24 +346,29 @@ impl<'a> PowerEventHandler<'a> {
                 new_topic_handlers,
                 new_status_manager,
                 new_monitoring_handle,
+                _startup_handle,
             )) => {
                 *self.client = new_client;
                 *self.eventloop = new_eventloop;
                 *self.topic_handlers = new_topic_handlers;
+                let mut new_status_manager = new_status_manager;
+                new_status_manager.restore_reconnect_count(self.status_manager.reconnect_count());
                 *self.status_manager = new_status_manager;
                 *self.system_monitor_handle = new_monitoring_handle;
 
                 info!("MQTT connection re-established successfully");
-                debug!("Successfully published 'On' status after resume");
             }
             Err(e) => {
                 error!("Failed to re-establish MQTT connection after resume: {}", e);
                 // Continue with the old connection and hope it recovers
             }
         }
+    }
 
+    /// Reconnect to D-Bus and recreate the suspend inhibitor
+    async fn restore_dbus_inhibitor(&mut self) {
         // Reconnect to D-Bus with retry
-        let max_retries = 3;
+        let max_retries = self.config.dbus_retry_attempts;
 
         // Try to reconnect to D-Bus
         if let Err(e) = self
@@ -1,31 +1,237 @@
-use crate::ha_mqtt::HomeAssistantComponent;
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{HomeAssistantComponent, MqttClient};
 use crate::utils::Config;
-use rumqttc::{AsyncClient, QoS};
+use crate::utils::config::StatusLabels;
+use rumqttc::QoS;
 use serde::Serialize;
-use tokio::time::{timeout, Duration};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, timeout};
 use tracing::{debug, info, warn};
 
+/// Lifecycle state of the daemon, as shown by the status sensor
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonState {
+    /// Connected to the broker, setting up entities
+    Starting,
+    Connected,
+    /// Connected, but metrics publishing keeps failing
+    Degraded,
+    Suspended,
+    ShuttingDown,
+    Off,
+    /// The process panicked, published by the panic hook
+    Crashed,
+}
+
+impl DaemonState {
+    /// Text published for this state
+    fn label(self, labels: &StatusLabels) -> &str {
+        match self {
+            DaemonState::Starting => &labels.starting,
+            DaemonState::Connected => &labels.on,
+            DaemonState::Degraded => &labels.degraded,
+            DaemonState::Suspended => &labels.suspended,
+            DaemonState::ShuttingDown => &labels.shutting_down,
+            DaemonState::Off => &labels.off,
+            DaemonState::Crashed => &labels.crashed,
+        }
+    }
+
+    /// Availability published with this state; `None` leaves it unchanged
+    fn availability(self) -> Option<bool> {
+        match self {
+            DaemonState::Starting => None,
+            DaemonState::Connected | DaemonState::Degraded => Some(true),
+            DaemonState::Suspended
+            | DaemonState::ShuttingDown
+            | DaemonState::Off
+            | DaemonState::Crashed => Some(false),
+        }
+    }
+
+    /// Whether the daemon is up and serving entities
+    pub fn is_running(self) -> bool {
+        matches!(self, DaemonState::Connected | DaemonState::Degraded)
+    }
+}
+
 #[derive(Serialize)]
 struct StatusData {
     status: String,
+    /// Machine-readable state, independent of the configured labels
+    state: DaemonState,
+    /// Broker reconnections since startup, omitted until the first one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reconnects: Option<u32>,
+}
+
+/// Availability payloads following Home Assistant's defaults
+pub const PAYLOAD_ONLINE: &str = "online";
+pub const PAYLOAD_OFFLINE: &str = "offline";
+
+/// Payloads published to availability topics, including the last will,
+/// and declared in discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvailabilityPayloads {
+    pub available: String,
+    pub not_available: String,
+}
+
+impl Default for AvailabilityPayloads {
+    fn default() -> Self {
+        Self {
+            available: PAYLOAD_ONLINE.to_string(),
+            not_available: PAYLOAD_OFFLINE.to_string(),
+        }
+    }
+}
+
+impl AvailabilityPayloads {
+    /// The payloads set by `payload_available` and `payload_not_available`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            available: config.payload_available.clone(),
+            not_available: config.payload_not_available.clone(),
+        }
+    }
+
+    /// Payload marking a topic online or offline
+    pub fn payload(&self, available: bool) -> &str {
+        if available {
+            &self.available
+        } else {
+            &self.not_available
+        }
+    }
+
+    /// Whether these are Home Assistant's defaults, which discovery can leave out
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
+#[derive(Clone)]
 pub struct StatusManager {
     hostname: String,
-    client: AsyncClient,
+    availability_topic: String,
+    /// Topic reflecting the system D-Bus connection, if published
+    dbus_availability_topic: Option<String>,
+    client: MqttClient,
+    /// Text published for each status
+    labels: StatusLabels,
+    /// Payloads published to the availability topics
+    payloads: AvailabilityPayloads,
+    /// Whether the initial connection has been acknowledged
+    connected_once: bool,
+    /// Number of ConnAcks received after the initial one
+    reconnect_count: u32,
+    /// Last published state, shared with clones publishing in the background
+    state: Arc<Mutex<Option<DaemonState>>>,
 }
 
 impl StatusManager {
-    pub fn new(hostname: String, client: AsyncClient) -> Self {
-        Self { hostname, client }
+    pub fn new(hostname: String, availability_topic: String, client: MqttClient) -> Self {
+        Self {
+            hostname,
+            availability_topic,
+            dbus_availability_topic: None,
+            client,
+            labels: StatusLabels::default(),
+            payloads: AvailabilityPayloads::default(),
+            connected_once: false,
+            reconnect_count: 0,
+            state: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Also publish D-Bus availability to `topic` in `publish_dbus_availability`
+    pub fn with_dbus_availability_topic(mut self, topic: Option<String>) -> Self {
+        self.dbus_availability_topic = topic;
+        self
+    }
+
+    /// Publish these texts instead of the default "Starting", "On", "Degraded", ...
+    pub fn with_status_labels(mut self, labels: StatusLabels) -> Self {
+        self.labels = labels;
+        self
     }
 
-    pub async fn publish_status(&self, status: &str) -> Result<(), Box<dyn std::error::Error>> {
+    /// Publish these instead of `online` and `offline` to the availability topics
+    pub fn with_availability_payloads(mut self, payloads: AvailabilityPayloads) -> Self {
+        self.payloads = payloads;
+        self
+    }
+
+    /// Record a ConnAck from the broker. Returns true if this was a reconnection.
+    pub fn record_conn_ack(&mut self) -> bool {
+        if self.connected_once {
+            self.reconnect_count += 1;
+            true
+        } else {
+            self.connected_once = true;
+            false
+        }
+    }
+
+    /// The last published state, if any
+    pub fn state(&self) -> Option<DaemonState> {
+        self.state.lock().ok().and_then(|state| *state)
+    }
+
+    /// Number of reconnections since startup
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnect_count
+    }
+
+    /// Carry over the count when the manager is recreated on resume.
+    /// The new connection's first ConnAck is still treated as the initial one.
+    pub fn restore_reconnect_count(&mut self, reconnect_count: u32) {
+        self.reconnect_count = reconnect_count;
+    }
+
+    /// Publish an online/offline payload to an availability topic
+    async fn publish_availability_to(&self, topic: &str, available: bool) -> Result<()> {
+        let payload = self.payloads.payload(available);
+        debug!("Publishing availability to '{}': {}", topic, payload);
+
+        match timeout(
+            Duration::from_secs(5),
+            self.client.publish(topic, QoS::AtLeastOnce, true, payload),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(Error::Timeout(format!(
+                "publishing availability '{}'",
+                payload
+            ))),
+        }
+    }
+
+    /// Publish the machine-readable availability used by the other entities
+    pub async fn publish_availability(&self, available: bool) -> Result<()> {
+        self.publish_availability_to(&self.availability_topic, available)
+            .await
+    }
+
+    /// Publish whether the system D-Bus is reachable; does nothing unless
+    /// a D-Bus availability topic is set
+    pub async fn publish_dbus_availability(&self, available: bool) -> Result<()> {
+        match &self.dbus_availability_topic {
+            Some(topic) => self.publish_availability_to(topic, available).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn publish_status(&self, status: &str, state: DaemonState) -> Result<()> {
         let status_data = StatusData {
             status: status.to_string(),
+            state,
+            reconnects: (self.reconnect_count > 0).then_some(self.reconnect_count),
         };
         let status_json = serde_json::to_string(&status_data)?;
-        let status_topic = format!("homeassistant/sensor/{}/status/state", self.hostname);
+        let status_topic = status_state_topic(&self.hostname);
 
         info!("Publishing status: {}", status);
 
@@ -42,7 +248,7 @@ impl StatusManager {
                     "Timeout publishing status '{}' to topic '{}'",
                     status, status_topic
                 );
-                return Err("Timeout publishing status".into());
+                return Err(Error::Timeout(format!("publishing status '{}'", status)));
             }
         }
 
@@ -50,32 +256,51 @@ impl StatusManager {
         Ok(())
     }
 
-    pub async fn publish_on(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.publish_status("On").await
+    /// Publish `state` to the status sensor, along with the matching availability
+    pub async fn publish_state(&mut self, state: DaemonState) -> Result<()> {
+        if let Some(available) = state.availability() {
+            self.publish_availability(available).await?;
+        }
+        self.publish_status(state.label(&self.labels), state)
+            .await?;
+        if let Ok(mut current) = self.state.lock() {
+            *current = Some(state);
+        }
+        Ok(())
     }
+}
 
-    pub async fn publish_off(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.publish_status("Off").await
-    }
+/// Status JSON for `state`, without a reconnect counter, for publishing
+/// outside of a `StatusManager`
+pub fn status_payload(labels: &StatusLabels, state: DaemonState) -> Result<String> {
+    let status_data = StatusData {
+        status: state.label(labels).to_string(),
+        state,
+        reconnects: None,
+    };
+    Ok(serde_json::to_string(&status_data)?)
+}
 
-    pub async fn publish_suspended(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.publish_status("Suspended").await
-    }
+/// JSON state topic of the status sensor
+pub fn status_state_topic(hostname: &str) -> String {
+    format!("homeassistant/sensor/{}/status/state", hostname)
 }
 
 /// Creates status sensor component
 pub fn create_status_component(config: &Config) -> (String, HomeAssistantComponent) {
     let component_id = format!("{}_status", config.hostname);
-    let state_topic = format!("homeassistant/sensor/{}/status/state", config.hostname);
+    let state_topic = status_state_topic(&config.hostname);
 
     let component = HomeAssistantComponent::sensor(
         format!("{} Status", config.hostname),
         component_id.clone(),
-        state_topic,
+        state_topic.clone(),
         None, // device_class
         None, // unit_of_measurement
-        "{{ value_json.status }}".to_string(),
-    );
+        Some("{{ value_json.status }}".to_string()),
+    )
+    .with_json_attributes(state_topic)
+    .with_entity_picture(config.status_entity_picture.clone());
 
     (component_id, component)
 }
@@ -0,0 +1,99 @@
+use thiserror::Error;
+
+/// Crate-wide error type, so callers can react to the kind of failure
+/// rather than just its message
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid or unreadable configuration
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    /// Failure reported by the MQTT client
+    #[error("MQTT error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+
+    /// Failure of the underlying MQTT connection (network, broker refusal)
+    #[error("MQTT connection error: {0}")]
+    Connection(Box<rumqttc::ConnectionError>),
+
+    /// Failure reported by the MQTT v5 client
+    #[error("MQTT v5 error: {0}")]
+    MqttV5(Box<rumqttc::v5::ClientError>),
+
+    /// Failure of the underlying MQTT v5 connection
+    #[error("MQTT v5 connection error: {0}")]
+    ConnectionV5(Box<rumqttc::v5::ConnectionError>),
+
+    /// Failure talking to the session or system D-Bus
+    #[error("D-Bus error: {0}")]
+    Dbus(#[from] zbus::Error),
+
+    /// Underlying I/O failure (files, signals, process spawning)
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// An executed command did not complete successfully
+    #[error("Command error: {0}")]
+    Command(String),
+
+    /// An executed command exited with a failure status; `code` is `None` if
+    /// it was killed by a signal, `stderr` holds the start of its error output
+    #[error("Command failed with exit code: {code:?}{}", stderr_suffix(.stderr))]
+    CommandExit { code: Option<i32>, stderr: String },
+
+    /// Failure serializing or deserializing a JSON payload
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// An operation did not complete within its deadline
+    #[error("Timeout: {0}")]
+    Timeout(String),
+}
+
+/// Appended to a failed command's message if it wrote anything to stderr
+fn stderr_suffix(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", stderr)
+    }
+}
+
+/// Convenience alias used throughout the crate
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+impl From<rumqttc::ConnectionError> for Error {
+    fn from(e: rumqttc::ConnectionError) -> Self {
+        Error::Connection(Box::new(e))
+    }
+}
+
+impl From<rumqttc::v5::ClientError> for Error {
+    fn from(e: rumqttc::v5::ClientError) -> Self {
+        Error::MqttV5(Box::new(e))
+    }
+}
+
+impl From<rumqttc::v5::ConnectionError> for Error {
+    fn from(e: rumqttc::v5::ConnectionError) -> Self {
+        Error::ConnectionV5(Box::new(e))
+    }
+}
+
+impl From<zbus::zvariant::Error> for Error {
+    fn from(e: zbus::zvariant::Error) -> Self {
+        Error::Dbus(e.into())
+    }
+}
+
+impl From<tracing_subscriber::filter::ParseError> for Error {
+    fn from(e: tracing_subscriber::filter::ParseError) -> Self {
+        Error::Config(format!("invalid log level: {}", e))
+    }
+}
@@ -0,0 +1,59 @@
+// Birth message - announces a running agent to monitoring systems on connect
+
+use super::MqttClient;
+use crate::error::Result;
+use crate::utils::{Config, VersionInfo};
+use rumqttc::QoS;
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Retained payload published to `birth_topic` after connecting
+#[derive(Serialize)]
+struct BirthMessage<'a> {
+    hostname: &'a str,
+    version: &'a str,
+    pid: u32,
+    /// Process start time in seconds since the Unix epoch
+    started_at: u64,
+    /// Topic carrying the LWT `payload_not_available` message that ends this lifecycle
+    availability_topic: &'a str,
+}
+
+/// Time the daemon started, fixed on first use so it survives reconnections
+fn started_at() -> u64 {
+    static STARTED_AT: OnceLock<u64> = OnceLock::new();
+    *STARTED_AT.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    })
+}
+
+/// Birth message JSON for the running process
+pub fn birth_message(config: &Config) -> Result<String> {
+    let birth = BirthMessage {
+        hostname: &config.hostname,
+        version: &VersionInfo::get().version,
+        pid: std::process::id(),
+        started_at: started_at(),
+        availability_topic: &config.availability_topic,
+    };
+    Ok(serde_json::to_string(&birth)?)
+}
+
+/// Publish the retained birth message to `topic`
+pub async fn publish_birth_message(
+    client: &MqttClient,
+    topic: &str,
+    birth_json: String,
+) -> Result<()> {
+    debug!("Publishing birth message to '{}': {}", topic, birth_json);
+    client
+        .publish(topic, QoS::AtLeastOnce, true, birth_json)
+        .await?;
+
+    Ok(())
+}
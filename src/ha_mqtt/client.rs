@@ -0,0 +1,363 @@
+// MQTT client abstraction - hides the differences between the v3.1.1 and v5 clients
+
+use rumqttc::{Outgoing, QoS};
+use std::borrow::Cow;
+use std::time::Duration;
+use tracing::debug;
+
+use super::subscriptions::SubscriptionTracker;
+use super::tls;
+use crate::error::{Error, Result};
+use crate::utils::config::MqttVersion;
+use crate::utils::{Config, VersionInfo};
+
+/// Capacity of the request channel between client and event loop
+const REQUEST_CHANNEL_CAPACITY: usize = 10;
+
+/// Interval of keep-alive pings while otherwise idle
+pub const KEEP_ALIVE_SECS: u64 = 5;
+
+/// How long an MQTT v5 broker keeps a persistent session after a disconnect
+const SESSION_EXPIRY_SECS: u32 = 24 * 60 * 60;
+
+/// MQTT client handle for either protocol version
+#[derive(Clone, Debug)]
+pub struct MqttClient {
+    handle: ClientHandle,
+    subscriptions: SubscriptionTracker,
+    /// Give up on publishes not queued within this time, instead of waiting
+    /// for room in a full request channel
+    publish_timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+enum ClientHandle {
+    V311(rumqttc::AsyncClient),
+    V5(rumqttc::v5::AsyncClient),
+}
+
+/// Event loop matching the protocol version of its `MqttClient`.
+/// Boxed since the event loops are large and moved around on re-initialization.
+pub enum MqttEventLoop {
+    V311(Box<rumqttc::EventLoop>),
+    V5(Box<rumqttc::v5::EventLoop>),
+}
+
+/// An event yielded by the event loop, in its version-specific form
+#[derive(Debug)]
+pub enum MqttEvent {
+    V311(rumqttc::Event),
+    V5(Box<rumqttc::v5::Event>),
+}
+
+/// Convert a QoS level to its v5 equivalent
+fn to_v5_qos(qos: QoS) -> rumqttc::v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => rumqttc::v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => rumqttc::v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => rumqttc::v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+/// User properties identifying the producing daemon and its version, attached to
+/// every v5 publish to help debugging setups with several producers
+fn producer_properties() -> rumqttc::v5::mqttbytes::v5::PublishProperties {
+    let version_info = VersionInfo::get();
+    rumqttc::v5::mqttbytes::v5::PublishProperties {
+        user_properties: vec![
+            ("producer".to_string(), version_info.name.clone()),
+            ("version".to_string(), version_info.version.clone()),
+        ],
+        ..Default::default()
+    }
+}
+
+impl MqttClient {
+    /// Create a client and event loop for the protocol version set in the config
+    pub fn new(config: &Config) -> Result<(Self, MqttEventLoop)> {
+        let transport = tls::transport(config)?;
+
+        let (handle, eventloop) = match config.mqtt_version {
+            MqttVersion::V311 => {
+                debug!("Creating MQTT v3.1.1 client");
+                let mut options = rumqttc::MqttOptions::new(
+                    &config.hostname,
+                    &config.mqtt_url,
+                    config.broker_port,
+                );
+                options.set_credentials(&config.username, &config.password);
+                options.set_keep_alive(Duration::from_secs(KEEP_ALIVE_SECS));
+                options.set_clean_session(config.clean_session);
+                if let Some(transport) = transport {
+                    options.set_transport(transport);
+                }
+                options.set_last_will(rumqttc::LastWill::new(
+                    &config.availability_topic,
+                    config.payload_not_available.as_str(),
+                    QoS::AtLeastOnce,
+                    true,
+                ));
+
+                let (client, mut eventloop) =
+                    rumqttc::AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+                if let Some(interface) = &config.bind_interface {
+                    let mut network_options = eventloop.network_options();
+                    network_options.set_bind_device(interface);
+                    eventloop.set_network_options(network_options);
+                }
+                (
+                    ClientHandle::V311(client),
+                    MqttEventLoop::V311(Box::new(eventloop)),
+                )
+            }
+            MqttVersion::V5 => {
+                debug!("Creating MQTT v5 client");
+                let mut options = rumqttc::v5::MqttOptions::new(
+                    &config.hostname,
+                    &config.mqtt_url,
+                    config.broker_port,
+                );
+                options.set_credentials(&config.username, &config.password);
+                options.set_keep_alive(Duration::from_secs(KEEP_ALIVE_SECS));
+                options.set_clean_start(config.clean_session);
+                if !config.clean_session {
+                    // A v5 session ends with the connection unless an expiry is set
+                    options.set_connect_properties(rumqttc::v5::mqttbytes::v5::ConnectProperties {
+                        session_expiry_interval: Some(SESSION_EXPIRY_SECS),
+                        ..rumqttc::v5::mqttbytes::v5::ConnectProperties::new()
+                    });
+                }
+                if let Some(transport) = transport {
+                    options.set_transport(transport);
+                }
+                if let Some(interface) = &config.bind_interface {
+                    let mut network_options = options.network_options();
+                    network_options.set_bind_device(interface);
+                    options.set_network_options(network_options);
+                }
+                options.set_last_will(rumqttc::v5::mqttbytes::v5::LastWill::new(
+                    &config.availability_topic,
+                    config.payload_not_available.as_str(),
+                    to_v5_qos(QoS::AtLeastOnce),
+                    true,
+                    None,
+                ));
+
+                let (client, eventloop) =
+                    rumqttc::v5::AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+                (
+                    ClientHandle::V5(client),
+                    MqttEventLoop::V5(Box::new(eventloop)),
+                )
+            }
+        };
+
+        let client = Self {
+            handle,
+            subscriptions: SubscriptionTracker::default(),
+            publish_timeout: None,
+        };
+        Ok((client, eventloop))
+    }
+
+    /// Subscriptions awaiting the broker's acknowledgement
+    pub fn subscriptions(&self) -> &SubscriptionTracker {
+        &self.subscriptions
+    }
+
+    /// A handle whose publishes fail with `Error::Timeout` if the request
+    /// channel stays full for `timeout`, e.g. while the broker is unreachable
+    pub fn with_publish_timeout(mut self, timeout: Duration) -> Self {
+        self.publish_timeout = Some(timeout);
+        self
+    }
+
+    /// Publish a message to a topic. With MQTT v5 the producer user properties are attached.
+    pub async fn publish<S, P>(&self, topic: S, qos: QoS, retain: bool, payload: P) -> Result<()>
+    where
+        S: Into<String>,
+        P: Into<Vec<u8>>,
+    {
+        let Some(timeout) = self.publish_timeout else {
+            return self.publish_now(topic, qos, retain, payload).await;
+        };
+        let topic = topic.into();
+        match tokio::time::timeout(
+            timeout,
+            self.publish_now(topic.clone(), qos, retain, payload),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(Error::Timeout(format!(
+                "MQTT request queue still full after {}s, publish to '{}' dropped",
+                timeout.as_secs(),
+                topic
+            ))),
+        }
+    }
+
+    async fn publish_now<S, P>(&self, topic: S, qos: QoS, retain: bool, payload: P) -> Result<()>
+    where
+        S: Into<String>,
+        P: Into<Vec<u8>>,
+    {
+        match &self.handle {
+            ClientHandle::V311(client) => client.publish(topic, qos, retain, payload).await?,
+            ClientHandle::V5(client) => {
+                client
+                    .publish_with_properties(
+                        topic,
+                        to_v5_qos(qos),
+                        retain,
+                        payload.into(),
+                        producer_properties(),
+                    )
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe to a topic. This returns once the request is queued; the
+    /// broker's acknowledgement is checked through `subscriptions`.
+    pub async fn subscribe<S: Into<String>>(&self, topic: S, qos: QoS) -> Result<()> {
+        let topic = topic.into();
+        match &self.handle {
+            ClientHandle::V311(client) => client.subscribe(topic.clone(), qos).await?,
+            ClientHandle::V5(client) => client.subscribe(topic.clone(), to_v5_qos(qos)).await?,
+        }
+        self.subscriptions.record_request(topic);
+        Ok(())
+    }
+
+    /// Send a disconnect request to the broker
+    pub async fn disconnect(&self) -> Result<()> {
+        match &self.handle {
+            ClientHandle::V311(client) => client.disconnect().await?,
+            ClientHandle::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+impl MqttEventLoop {
+    /// Drive the connection and return the next event
+    pub async fn poll(&mut self) -> Result<MqttEvent> {
+        match self {
+            Self::V311(eventloop) => Ok(MqttEvent::V311(eventloop.poll().await?)),
+            Self::V5(eventloop) => Ok(MqttEvent::V5(Box::new(eventloop.poll().await?))),
+        }
+    }
+}
+
+impl MqttEvent {
+    /// Topic and payload if this is an incoming publish
+    pub fn as_publish(&self) -> Option<(Cow<'_, str>, &[u8])> {
+        match self {
+            Self::V311(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                Some((Cow::Borrowed(publish.topic.as_str()), &publish.payload))
+            }
+            Self::V5(event) => match event.as_ref() {
+                rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::Publish(publish)) => {
+                    Some((String::from_utf8_lossy(&publish.topic), &publish.payload))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether this is an incoming publish the broker replayed from its
+    /// retained store, e.g. right after subscribing
+    pub fn is_retained_publish(&self) -> bool {
+        match self {
+            Self::V311(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                publish.retain
+            }
+            Self::V5(event) => matches!(
+                event.as_ref(),
+                rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::Publish(publish))
+                    if publish.retain
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether the broker acknowledged a (re)connection
+    pub fn is_conn_ack(&self) -> bool {
+        match self {
+            Self::V311(event) => {
+                matches!(event, rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)))
+            }
+            Self::V5(event) => matches!(
+                event.as_ref(),
+                rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::ConnAck(_))
+            ),
+        }
+    }
+
+    /// Whether a ConnAck reports that the broker resumed an existing session
+    pub fn session_present(&self) -> bool {
+        match self {
+            Self::V311(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(ack))) => {
+                ack.session_present
+            }
+            Self::V5(event) => matches!(
+                event.as_ref(),
+                rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::ConnAck(ack))
+                    if ack.session_present
+            ),
+            _ => false,
+        }
+    }
+
+    /// Packet id of a subscribe request sent to the broker
+    pub fn outgoing_subscribe(&self) -> Option<u16> {
+        match self {
+            Self::V311(rumqttc::Event::Outgoing(Outgoing::Subscribe(pkid))) => Some(*pkid),
+            Self::V5(event) => match event.as_ref() {
+                rumqttc::v5::Event::Outgoing(Outgoing::Subscribe(pkid)) => Some(*pkid),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Packet id of a SubAck and whether the broker accepted every topic in it
+    pub fn sub_ack(&self) -> Option<(u16, bool)> {
+        match self {
+            Self::V311(rumqttc::Event::Incoming(rumqttc::Packet::SubAck(ack))) => {
+                let accepted = ack
+                    .return_codes
+                    .iter()
+                    .all(|code| matches!(code, rumqttc::SubscribeReasonCode::Success(_)));
+                Some((ack.pkid, accepted))
+            }
+            Self::V5(event) => match event.as_ref() {
+                rumqttc::v5::Event::Incoming(rumqttc::v5::Incoming::SubAck(ack)) => {
+                    let accepted = ack.return_codes.iter().all(|code| {
+                        matches!(
+                            code,
+                            rumqttc::v5::mqttbytes::v5::SubscribeReasonCode::Success(_)
+                        )
+                    });
+                    Some((ack.pkid, accepted))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether a disconnect request was sent to the broker
+    pub fn is_outgoing_disconnect(&self) -> bool {
+        match self {
+            Self::V311(event) => matches!(event, rumqttc::Event::Outgoing(Outgoing::Disconnect)),
+            Self::V5(event) => matches!(
+                event.as_ref(),
+                rumqttc::v5::Event::Outgoing(Outgoing::Disconnect)
+            ),
+        }
+    }
+}
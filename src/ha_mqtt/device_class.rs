@@ -0,0 +1,163 @@
+// Home Assistant device classes - catches typos in device_class and unit strings
+// before they reach discovery and silently produce broken entities
+
+use crate::error::{Error, Result};
+
+/// Device classes accepted by HA's MQTT sensor
+const SENSOR_DEVICE_CLASSES: &[&str] = &[
+    "apparent_power",
+    "aqi",
+    "area",
+    "atmospheric_pressure",
+    "battery",
+    "blood_glucose_concentration",
+    "carbon_dioxide",
+    "carbon_monoxide",
+    "conductivity",
+    "current",
+    "data_rate",
+    "data_size",
+    "date",
+    "distance",
+    "duration",
+    "energy",
+    "energy_distance",
+    "energy_storage",
+    "enum",
+    "frequency",
+    "gas",
+    "humidity",
+    "illuminance",
+    "irradiance",
+    "moisture",
+    "monetary",
+    "nitrogen_dioxide",
+    "nitrogen_monoxide",
+    "nitrous_oxide",
+    "ozone",
+    "ph",
+    "pm1",
+    "pm10",
+    "pm25",
+    "power",
+    "power_factor",
+    "precipitation",
+    "precipitation_intensity",
+    "pressure",
+    "reactive_power",
+    "signal_strength",
+    "sound_pressure",
+    "speed",
+    "sulphur_dioxide",
+    "temperature",
+    "timestamp",
+    "volatile_organic_compounds",
+    "volatile_organic_compounds_parts",
+    "voltage",
+    "volume",
+    "volume_flow_rate",
+    "volume_storage",
+    "water",
+    "weight",
+    "wind_direction",
+    "wind_speed",
+];
+
+/// Device classes accepted by HA's MQTT binary sensor
+const BINARY_SENSOR_DEVICE_CLASSES: &[&str] = &[
+    "battery",
+    "battery_charging",
+    "carbon_monoxide",
+    "cold",
+    "connectivity",
+    "door",
+    "garage_door",
+    "gas",
+    "heat",
+    "light",
+    "lock",
+    "moisture",
+    "motion",
+    "moving",
+    "occupancy",
+    "opening",
+    "plug",
+    "power",
+    "presence",
+    "problem",
+    "running",
+    "safety",
+    "smoke",
+    "sound",
+    "tamper",
+    "update",
+    "vibration",
+    "window",
+];
+
+/// Units HA requires for the device classes this daemon is likely to use
+const SENSOR_UNITS: &[(&str, &[&str])] = &[
+    (
+        "data_size",
+        &[
+            "bit", "kbit", "Mbit", "Gbit", "B", "kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB",
+            "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB",
+        ],
+    ),
+    (
+        "data_rate",
+        &[
+            "bit/s", "kbit/s", "Mbit/s", "Gbit/s", "B/s", "kB/s", "MB/s", "GB/s", "KiB/s", "MiB/s",
+            "GiB/s",
+        ],
+    ),
+    ("frequency", &["Hz", "kHz", "MHz", "GHz"]),
+    ("temperature", &["°C", "°F", "K"]),
+    ("duration", &["d", "h", "min", "s", "ms"]),
+    ("power", &["W", "kW", "MW", "GW", "TW"]),
+    ("battery", &["%"]),
+    ("humidity", &["%"]),
+];
+
+/// Check a sensor's device class and, where HA is strict, its unit
+pub fn validate_sensor(device_class: Option<&str>, unit: Option<&str>) -> Result<()> {
+    let Some(device_class) = device_class else {
+        return Ok(());
+    };
+    if !SENSOR_DEVICE_CLASSES.contains(&device_class) {
+        return Err(Error::Config(format!(
+            "Unknown sensor device_class '{}'",
+            device_class
+        )));
+    }
+
+    let allowed_units = SENSOR_UNITS
+        .iter()
+        .find(|(class, _)| *class == device_class)
+        .map(|(_, units)| *units);
+    if let Some(allowed_units) = allowed_units
+        && !unit.is_some_and(|unit| allowed_units.contains(&unit))
+    {
+        return Err(Error::Config(format!(
+            "Unit {:?} is not valid for device_class '{}', expected one of: {}",
+            unit,
+            device_class,
+            allowed_units.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check a binary sensor's device class
+pub fn validate_binary_sensor(device_class: Option<&str>) -> Result<()> {
+    match device_class {
+        Some(device_class) if !BINARY_SENSOR_DEVICE_CLASSES.contains(&device_class) => {
+            Err(Error::Config(format!(
+                "Unknown binary_sensor device_class '{}'",
+                device_class
+            )))
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,22 +1,25 @@
-use crate::utils::{Config, VersionInfo};
-use rumqttc::{AsyncClient, QoS};
+use super::{MqttClient, device_class};
+use crate::error::{Error, Result};
+use crate::utils::{Config, DiscoveryMode, VersionInfo};
+use rumqttc::QoS;
 use serde::Serialize;
 use std::collections::HashMap;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 /// Generic function to publish Home Assistant discovery messages
 pub async fn publish_discovery<T: Serialize>(
-    client: &AsyncClient,
+    client: &MqttClient,
     discovery_topic: &str,
     discovery_payload: &T,
+    qos: QoS,
     retain: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<()> {
     let discovery_json = serde_json::to_string(discovery_payload)?;
 
     debug!("Publishing discovery to: {}", discovery_topic);
     debug!("Discovery payload: {}", discovery_json);
     client
-        .publish(discovery_topic, QoS::AtLeastOnce, retain, discovery_json)
+        .publish(discovery_topic, qos, retain, discovery_json)
         .await?;
 
     Ok(())
@@ -37,14 +40,26 @@ pub enum ComponentType {
         device_class: Option<String>,
         #[serde(rename = "unit_of_meas", skip_serializing_if = "Option::is_none")]
         unit_of_measurement: Option<String>,
-        #[serde(rename = "val_tpl")]
-        value_template: String,
+        #[serde(rename = "val_tpl", skip_serializing_if = "Option::is_none")]
+        value_template: Option<String>,
+        /// Decimal places HA displays; the published value is not rounded
+        #[serde(rename = "sug_dsp_prc", skip_serializing_if = "Option::is_none")]
+        suggested_display_precision: Option<u8>,
+    },
+    #[serde(rename = "binary_sensor")]
+    BinarySensor {
+        #[serde(rename = "stat_t")]
+        state_topic: String,
+        #[serde(rename = "dev_cla", skip_serializing_if = "Option::is_none")]
+        device_class: Option<String>,
     },
     Switch {
         #[serde(rename = "cmd_t")]
         command_topic: String,
         #[serde(rename = "stat_t")]
         state_topic: String,
+        #[serde(rename = "opt")]
+        optimistic: bool,
     },
     Notify {
         #[serde(rename = "cmd_t")]
@@ -52,23 +67,197 @@ pub enum ComponentType {
     },
 }
 
+impl ComponentType {
+    /// HA platform name, as used in the `p` field and discovery topics
+    pub fn platform(&self) -> &'static str {
+        match self {
+            ComponentType::Button { .. } => "button",
+            ComponentType::Sensor { .. } => "sensor",
+            ComponentType::BinarySensor { .. } => "binary_sensor",
+            ComponentType::Switch { .. } => "switch",
+            ComponentType::Notify { .. } => "notify",
+        }
+    }
+}
+
+/// One entry of a component's availability list
+#[derive(Serialize, Clone)]
+pub struct Availability {
+    #[serde(rename = "t")]
+    pub topic: String,
+    /// Template rendering the payload to `online` or `offline`, e.g. for a JSON topic
+    #[serde(rename = "val_tpl", skip_serializing_if = "Option::is_none")]
+    pub value_template: Option<String>,
+    /// Payload meaning available, if not `online`
+    #[serde(rename = "pl_avail", skip_serializing_if = "Option::is_none")]
+    pub payload_available: Option<String>,
+    /// Payload meaning unavailable, if not `offline`
+    #[serde(rename = "pl_not_avail", skip_serializing_if = "Option::is_none")]
+    pub payload_not_available: Option<String>,
+}
+
+impl Availability {
+    /// Follow `topic`, whose payload is `online` or `offline`
+    pub fn new(topic: String) -> Self {
+        Self {
+            topic,
+            value_template: None,
+            payload_available: None,
+            payload_not_available: None,
+        }
+    }
+
+    /// Extract `online` or `offline` from the payload with `template`
+    pub fn with_template(mut self, template: String) -> Self {
+        self.value_template = Some(template);
+        self
+    }
+
+    /// Expect `available` and `not_available` instead of `online` and `offline`
+    pub fn with_payloads(mut self, available: String, not_available: String) -> Self {
+        self.payload_available = Some(available);
+        self.payload_not_available = Some(not_available);
+        self
+    }
+}
+
 /// A Home Assistant component with metadata
 #[derive(Serialize, Clone)]
 pub struct HomeAssistantComponent {
     pub name: String,
     pub unique_id: String,
+    /// Explicit object id HA builds the entity id from, instead of the name
+    #[serde(rename = "obj_id", skip_serializing_if = "Option::is_none")]
+    pub object_id: Option<String>,
+    #[serde(rename = "avty_t", skip_serializing_if = "Option::is_none")]
+    pub availability_topic: Option<String>,
+    /// Template extracting `online` or `offline` from `availability_topic`
+    #[serde(rename = "avty_tpl", skip_serializing_if = "Option::is_none")]
+    pub availability_template: Option<String>,
+    /// Payloads of `availability_topic`, if not `online` and `offline`
+    #[serde(rename = "pl_avail", skip_serializing_if = "Option::is_none")]
+    pub payload_available: Option<String>,
+    #[serde(rename = "pl_not_avail", skip_serializing_if = "Option::is_none")]
+    pub payload_not_available: Option<String>,
+    /// Several availability topics, combined according to `availability_mode`
+    #[serde(rename = "avty", skip_serializing_if = "Vec::is_empty")]
+    pub availability: Vec<Availability>,
+    /// How HA combines `availability`: "all", "any" or "latest"
+    #[serde(rename = "avty_mode", skip_serializing_if = "Option::is_none")]
+    pub availability_mode: Option<String>,
+    /// Whether HA enables the entity when it is first discovered
+    #[serde(rename = "en", skip_serializing_if = "Option::is_none")]
+    pub enabled_by_default: Option<bool>,
+    #[serde(rename = "json_attr_t", skip_serializing_if = "Option::is_none")]
+    pub json_attributes_topic: Option<String>,
+    /// "config" or "diagnostic"; unset for regular entities
+    #[serde(rename = "ent_cat", skip_serializing_if = "Option::is_none")]
+    pub entity_category: Option<String>,
+    /// Picture URL HA shows instead of the entity icon
+    #[serde(rename = "ent_pic", skip_serializing_if = "Option::is_none")]
+    pub entity_picture: Option<String>,
+    /// Encoding of the payloads HA receives and sends, if not UTF-8;
+    /// an empty string means raw bytes
+    #[serde(rename = "e", skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
     #[serde(flatten)]
     pub component_type: ComponentType,
 }
 
 impl HomeAssistantComponent {
-    /// Create a new button component
-    pub fn button(name: String, unique_id: String, command_topic: String) -> Self {
+    /// Create a component with no optional settings
+    fn new(name: String, unique_id: String, component_type: ComponentType) -> Self {
         Self {
             name,
             unique_id,
-            component_type: ComponentType::Button { command_topic },
+            object_id: None,
+            availability_topic: None,
+            availability_template: None,
+            availability: Vec::new(),
+            availability_mode: None,
+            payload_available: None,
+            payload_not_available: None,
+            enabled_by_default: None,
+            json_attributes_topic: None,
+            entity_category: None,
+            entity_picture: None,
+            encoding: None,
+            component_type,
+        }
+    }
+
+    /// Set the object id HA derives the entity id from, if given
+    pub fn with_object_id(mut self, object_id: Option<String>) -> Self {
+        self.object_id = object_id;
+        self
+    }
+
+    /// Expose the JSON object on `topic` as entity attributes
+    pub fn with_json_attributes(mut self, topic: String) -> Self {
+        self.json_attributes_topic = Some(topic);
+        self
+    }
+
+    /// Set whether HA enables the entity when it is first discovered
+    pub fn with_enabled_by_default(mut self, enabled: bool) -> Self {
+        self.enabled_by_default = Some(enabled);
+        self
+    }
+
+    /// Group the entity under HA's "config" or "diagnostic" section
+    pub fn with_entity_category(mut self, category: &str) -> Self {
+        self.entity_category = Some(category.to_string());
+        self
+    }
+
+    /// Show the picture at this URL instead of the entity icon, if given
+    pub fn with_entity_picture(mut self, url: Option<String>) -> Self {
+        self.entity_picture = url;
+        self
+    }
+
+    /// Declare the payload encoding, if given; `Some("")` for raw bytes
+    pub fn with_encoding(mut self, encoding: Option<String>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Have HA display this many decimal places; only applies to sensors
+    pub fn with_suggested_display_precision(mut self, precision: Option<u8>) -> Self {
+        if let ComponentType::Sensor {
+            suggested_display_precision,
+            ..
+        } = &mut self.component_type
+        {
+            *suggested_display_precision = precision;
         }
+        self
+    }
+
+    /// Follow a single availability topic, or several combined according to `mode`
+    pub fn with_availability(mut self, mut entries: Vec<Availability>, mode: &str) -> Self {
+        if entries.len() == 1 {
+            let entry = entries.remove(0);
+            self.availability_topic = Some(entry.topic);
+            self.availability_template = entry.value_template;
+            self.payload_available = entry.payload_available;
+            self.payload_not_available = entry.payload_not_available;
+            self.availability = Vec::new();
+            self.availability_mode = None;
+        } else {
+            self.availability_topic = None;
+            self.availability_template = None;
+            self.payload_available = None;
+            self.payload_not_available = None;
+            self.availability = entries;
+            self.availability_mode = Some(mode.to_string());
+        }
+        self
+    }
+
+    /// Create a new button component
+    pub fn button(name: String, unique_id: String, command_topic: String) -> Self {
+        Self::new(name, unique_id, ComponentType::Button { command_topic })
     }
 
     /// Create a new sensor component
@@ -78,18 +267,36 @@ impl HomeAssistantComponent {
         state_topic: String,
         device_class: Option<String>,
         unit_of_measurement: Option<String>,
-        value_template: String,
+        value_template: Option<String>,
     ) -> Self {
-        Self {
+        Self::new(
             name,
             unique_id,
-            component_type: ComponentType::Sensor {
+            ComponentType::Sensor {
                 state_topic,
                 device_class,
                 unit_of_measurement,
                 value_template,
+                suggested_display_precision: None,
             },
-        }
+        )
+    }
+
+    /// Create a new binary sensor component, whose state topic carries `ON`/`OFF`
+    pub fn binary_sensor(
+        name: String,
+        unique_id: String,
+        state_topic: String,
+        device_class: Option<String>,
+    ) -> Self {
+        Self::new(
+            name,
+            unique_id,
+            ComponentType::BinarySensor {
+                state_topic,
+                device_class,
+            },
+        )
     }
 
     /// Create a new switch component
@@ -98,23 +305,39 @@ impl HomeAssistantComponent {
         unique_id: String,
         command_topic: String,
         state_topic: String,
+        optimistic: bool,
     ) -> Self {
-        Self {
+        Self::new(
             name,
             unique_id,
-            component_type: ComponentType::Switch {
+            ComponentType::Switch {
                 command_topic,
                 state_topic,
+                optimistic,
             },
-        }
+        )
     }
 
     /// Create a new notify component
     pub fn notify(name: String, unique_id: String, command_topic: String) -> Self {
-        Self {
-            name,
-            unique_id,
-            component_type: ComponentType::Notify { command_topic },
+        Self::new(name, unique_id, ComponentType::Notify { command_topic })
+    }
+
+    /// Check the device class and unit against the values HA accepts
+    pub fn validate(&self) -> Result<()> {
+        match &self.component_type {
+            ComponentType::Sensor {
+                device_class,
+                unit_of_measurement,
+                ..
+            } => device_class::validate_sensor(
+                device_class.as_deref(),
+                unit_of_measurement.as_deref(),
+            ),
+            ComponentType::BinarySensor { device_class, .. } => {
+                device_class::validate_binary_sensor(device_class.as_deref())
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -150,18 +373,51 @@ pub struct HomeAssistantDevice {
     pub manufacturer: String,
     #[serde(rename = "sw")]
     pub sw_version: String,
+    #[serde(rename = "hw", skip_serializing_if = "Option::is_none")]
+    pub hw_version: Option<String>,
+}
+
+/// DMI file holding the product version of the machine
+const DMI_PRODUCT_VERSION: &str = "/sys/class/dmi/id/product_version";
+
+/// Placeholders firmware vendors leave in unset DMI fields
+const DMI_PLACEHOLDERS: &[&str] = &[
+    "to be filled by o.e.m.",
+    "not specified",
+    "not applicable",
+    "default string",
+    "system version",
+    "none",
+];
+
+/// Read the machine's product version from DMI, ignoring vendor placeholders
+fn detect_hw_version() -> Option<String> {
+    let version = std::fs::read_to_string(DMI_PRODUCT_VERSION).ok()?;
+    let version = version.trim();
+    if version.is_empty() || DMI_PLACEHOLDERS.contains(&version.to_lowercase().as_str()) {
+        return None;
+    }
+    debug!("Detected hardware version from DMI: {}", version);
+    Some(version.to_string())
 }
 
 /// Creates a shared HomeAssistant device object using the hostname from config
-/// and the version from Cargo.toml at compile time
+/// and the version from Cargo.toml at compile time. The hardware version comes
+/// from `hw_version`, or DMI when unset; an empty `hw_version` omits it.
 pub fn create_shared_device(config: &Config) -> HomeAssistantDevice {
     let version_info = VersionInfo::get();
+    let hw_version = match config.hw_version.as_deref() {
+        Some("") => None,
+        Some(version) => Some(version.to_string()),
+        None => detect_hw_version(),
+    };
     HomeAssistantDevice {
         identifiers: config.hostname.clone(),
         name: config.hostname.clone(),
         model: "MQTT Daemon".to_string(),
         manufacturer: "Custom".to_string(),
         sw_version: version_info.version.clone(),
+        hw_version,
     }
 }
 
@@ -169,7 +425,7 @@ pub fn create_shared_device(config: &Config) -> HomeAssistantDevice {
 pub fn create_shared_origin() -> HomeAssistantOrigin {
     let version_info = VersionInfo::get();
     HomeAssistantOrigin {
-        name: "MQTT Agent".to_string(),
+        name: version_info.name.clone(),
         sw_version: version_info.version.clone(),
         support_url: version_info.repository.clone(),
     }
@@ -221,27 +477,550 @@ impl DeviceDiscoveryBuilder {
     }
 }
 
-/// Publish unified device discovery with all components
-pub async fn publish_unified_discovery(
-    client: &AsyncClient,
+/// Discovery topic of a single component, `<prefix>/<platform>/<id>/config`
+pub fn component_discovery_topic(prefix: &str, platform: &str, component_id: &str) -> String {
+    format!("{}/{}/{}/config", prefix, platform, component_id)
+}
+
+/// Per-entity discovery topic (`homeassistant/<platform>/<id>/config`) used
+/// before device-based discovery
+pub fn legacy_discovery_topic(platform: &str, component_id: &str) -> String {
+    component_discovery_topic("homeassistant", platform, component_id)
+}
+
+/// Clear the given legacy per-entity discovery topics, so those retained
+/// configs do not linger next to the unified device
+pub async fn clear_legacy_discovery(client: &MqttClient, legacy_topics: &[String]) -> Result<()> {
+    for legacy_topic in legacy_topics {
+        debug!("Clearing legacy discovery topic: {}", legacy_topic);
+        // An empty retained payload removes the retained message on the broker
+        client
+            .publish(legacy_topic, QoS::AtLeastOnce, true, "")
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Reject components sharing an id, e.g. a button and a switch with the same
+/// name, which would overwrite each other in the device discovery's components
+pub fn check_unique_component_ids(components: &[(String, HomeAssistantComponent)]) -> Result<()> {
+    let mut seen: HashMap<&str, &HomeAssistantComponent> = HashMap::new();
+    for (component_id, component) in components {
+        if let Some(existing) = seen.insert(component_id, component) {
+            return Err(Error::Config(format!(
+                "The {} '{}' and the {} '{}' both get the entity id '{}'; rename one of them",
+                existing.component_type.platform(),
+                existing.name,
+                component.component_type.platform(),
+                component.name,
+                component_id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Serialized discovery messages, as pairs of topic and payload
+#[derive(Debug)]
+pub struct DiscoveryMessages {
+    /// Whether each component is announced on its own topic instead of in
+    /// one device discovery
+    pub per_component: bool,
+    pub messages: Vec<(String, String)>,
+}
+
+impl DiscoveryMessages {
+    /// Whether a message is published to `topic`
+    pub fn contains_topic(&self, topic: &str) -> bool {
+        self.messages
+            .iter()
+            .any(|(message_topic, _)| message_topic == topic)
+    }
+}
+
+/// Single-component discovery payload: the component with the device and
+/// origin, but without the platform, which is part of the topic
+fn component_discovery_payload(
+    device_discovery: &HomeAssistantDeviceDiscovery,
+    component: &HomeAssistantComponent,
+) -> Result<String> {
+    let mut payload = serde_json::to_value(component)?;
+    if let Some(fields) = payload.as_object_mut() {
+        fields.remove("p");
+        fields.insert(
+            "dev".to_string(),
+            serde_json::to_value(&device_discovery.device)?,
+        );
+        fields.insert(
+            "o".to_string(),
+            serde_json::to_value(&device_discovery.origin)?,
+        );
+    }
+    Ok(payload.to_string())
+}
+
+/// Build the discovery messages for all components under every prefix: one
+/// device discovery, or one config per component with `discovery_mode =
+/// "per_component"` or when the device discovery exceeds `discovery_max_payload_bytes`.
+/// Fails if there are no components, since Home Assistant does not define how
+/// a device discovery with an empty `cmps` map is handled.
+pub fn build_discovery_messages(
     config: &Config,
     components: Vec<(String, HomeAssistantComponent)>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<DiscoveryMessages> {
+    if components.is_empty() {
+        return Err(Error::Config(format!(
+            "Refusing to publish device discovery for '{}' without any components",
+            config.hostname
+        )));
+    }
+
+    for (component_id, component) in &components {
+        if let Err(e) = component.validate() {
+            warn!(
+                "Component '{}' may be rejected by Home Assistant: {}",
+                component_id, e
+            );
+        }
+    }
+
     let device_discovery = DeviceDiscoveryBuilder::new(config)
         .add_components(components)
         .build();
 
-    debug!(
-        "Publishing unified device discovery with {} components",
-        device_discovery.components.len()
-    );
-    publish_discovery(
-        client,
-        &config.device_discovery_topic,
-        &device_discovery,
-        true,
-    )
-    .await?;
+    if config.discovery_mode == DiscoveryMode::Device {
+        let discovery_json = serde_json::to_string(&device_discovery)?;
+        match config.discovery_max_payload_bytes {
+            Some(max_bytes) if discovery_json.len() > max_bytes => {
+                info!(
+                    "Device discovery is {} bytes, more than discovery_max_payload_bytes ({}), publishing one config per component instead",
+                    discovery_json.len(),
+                    max_bytes
+                );
+            }
+            _ => {
+                // The same payload goes under every prefix, so each Home Assistant
+                // instance uses the same state and command topics
+                let messages = config
+                    .device_discovery_topics
+                    .iter()
+                    .map(|topic| (topic.clone(), discovery_json.clone()))
+                    .collect();
+                return Ok(DiscoveryMessages {
+                    per_component: false,
+                    messages,
+                });
+            }
+        }
+    }
+
+    let mut component_ids: Vec<&String> = device_discovery.components.keys().collect();
+    component_ids.sort();
+    let mut messages = Vec::new();
+    for component_id in component_ids {
+        let component = &device_discovery.components[component_id];
+        let payload = component_discovery_payload(&device_discovery, component)?;
+        if let Some(max_bytes) = config.discovery_max_payload_bytes
+            && payload.len() > max_bytes
+        {
+            warn!(
+                "Discovery of '{}' is {} bytes, more than discovery_max_payload_bytes ({})",
+                component_id,
+                payload.len(),
+                max_bytes
+            );
+        }
+        for prefix in &config.discovery_prefixes {
+            let topic = component_discovery_topic(
+                prefix,
+                component.component_type.platform(),
+                component_id,
+            );
+            messages.push((topic, payload.clone()));
+        }
+    }
+    Ok(DiscoveryMessages {
+        per_component: true,
+        messages,
+    })
+}
+
+/// Publish discovery messages, retained
+pub async fn publish_discovery_messages(
+    client: &MqttClient,
+    discovery: &DiscoveryMessages,
+    qos: QoS,
+) -> Result<()> {
+    debug!("Publishing {} discovery messages", discovery.messages.len());
+    for (topic, payload) in &discovery.messages {
+        debug!("Publishing discovery to: {}", topic);
+        debug!("Discovery payload: {}", payload);
+        client.publish(topic, qos, true, payload.clone()).await?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SAMPLE_CONFIG: &str = r#"
+        hostname = "test-host"
+        mqtt_url = "broker.lan"
+        mqtt_port = 1883
+        username = "user"
+        password = "secret"
+        log_level = "info"
+        update_interval_ms = 5000
+        hw_version = "rev1"
+    "#;
+
+    fn sample_config() -> Config {
+        Config::from_toml(SAMPLE_CONFIG).expect("sample config is valid")
+    }
+
+    fn to_json<T: Serialize>(value: &T) -> serde_json::Value {
+        serde_json::to_value(value).expect("payload serializes")
+    }
+
+    #[test]
+    fn device_discovery_has_device_origin_and_components() {
+        let config = sample_config();
+        let discovery = DeviceDiscoveryBuilder::new(&config)
+            .add_component(
+                "test-host_suspend".to_string(),
+                HomeAssistantComponent::button(
+                    "Suspend".to_string(),
+                    "test-host_suspend".to_string(),
+                    "homeassistant/button/test-host/suspend/set".to_string(),
+                ),
+            )
+            .build();
+        let version = VersionInfo::get();
+
+        assert_eq!(
+            to_json(&discovery),
+            json!({
+                "dev": {
+                    "ids": "test-host",
+                    "name": "test-host",
+                    "mdl": "MQTT Daemon",
+                    "mf": "Custom",
+                    "sw": version.version,
+                    "hw": "rev1",
+                },
+                "o": {
+                    "name": version.name,
+                    "sw": version.version,
+                    "url": version.repository,
+                },
+                "cmps": {
+                    "test-host_suspend": {
+                        "p": "button",
+                        "name": "Suspend",
+                        "unique_id": "test-host_suspend",
+                        "cmd_t": "homeassistant/button/test-host/suspend/set",
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn empty_hw_version_is_omitted() {
+        let config =
+            Config::from_toml(&SAMPLE_CONFIG.replace("\"rev1\"", "\"\"")).expect("config is valid");
+        let device = to_json(&create_shared_device(&config));
+        assert!(device.get("hw").is_none());
+    }
+
+    #[test]
+    fn sensor_serializes_short_keys() {
+        let sensor = HomeAssistantComponent::sensor(
+            "Memory Free".to_string(),
+            "test-host_memory_free".to_string(),
+            "homeassistant/sensor/test-host/system_performance/state".to_string(),
+            Some("data_size".to_string()),
+            Some("GB".to_string()),
+            Some("{{ value_json.memory_free }}".to_string()),
+        )
+        .with_suggested_display_precision(Some(2))
+        .with_enabled_by_default(false)
+        .with_availability(
+            vec![Availability::new(
+                "homeassistant/sensor/test-host/memory_free/availability".to_string(),
+            )],
+            "all",
+        );
+
+        assert_eq!(
+            to_json(&sensor),
+            json!({
+                "p": "sensor",
+                "name": "Memory Free",
+                "unique_id": "test-host_memory_free",
+                "stat_t": "homeassistant/sensor/test-host/system_performance/state",
+                "dev_cla": "data_size",
+                "unit_of_meas": "GB",
+                "val_tpl": "{{ value_json.memory_free }}",
+                "sug_dsp_prc": 2,
+                "en": false,
+                "avty_t": "homeassistant/sensor/test-host/memory_free/availability",
+            })
+        );
+    }
+
+    #[test]
+    fn binary_sensor_serializes_short_keys() {
+        let sensor = HomeAssistantComponent::binary_sensor(
+            "System Health".to_string(),
+            "test-host_health".to_string(),
+            "homeassistant/binary_sensor/test-host/health/state".to_string(),
+            Some("problem".to_string()),
+        );
+
+        assert_eq!(
+            to_json(&sensor),
+            json!({
+                "p": "binary_sensor",
+                "name": "System Health",
+                "unique_id": "test-host_health",
+                "stat_t": "homeassistant/binary_sensor/test-host/health/state",
+                "dev_cla": "problem",
+            })
+        );
+    }
+
+    #[test]
+    fn switch_serializes_short_keys() {
+        let switch = HomeAssistantComponent::switch(
+            "VPN".to_string(),
+            "test-host_vpn".to_string(),
+            "homeassistant/switch/test-host/vpn/set".to_string(),
+            "homeassistant/switch/test-host/vpn/state".to_string(),
+            true,
+        )
+        .with_object_id(Some("vpn".to_string()));
+
+        assert_eq!(
+            to_json(&switch),
+            json!({
+                "p": "switch",
+                "name": "VPN",
+                "unique_id": "test-host_vpn",
+                "obj_id": "vpn",
+                "cmd_t": "homeassistant/switch/test-host/vpn/set",
+                "stat_t": "homeassistant/switch/test-host/vpn/state",
+                "opt": true,
+            })
+        );
+    }
+
+    #[test]
+    fn notify_serializes_short_keys() {
+        let notify = HomeAssistantComponent::notify(
+            "Notifications".to_string(),
+            "test-host_notifications".to_string(),
+            "homeassistant/notify/test-host/notifications/set".to_string(),
+        )
+        .with_entity_category("config")
+        .with_json_attributes("homeassistant/notify/test-host/attributes".to_string())
+        .with_entity_picture(Some("https://example.com/bell.png".to_string()));
+
+        assert_eq!(
+            to_json(&notify),
+            json!({
+                "p": "notify",
+                "name": "Notifications",
+                "unique_id": "test-host_notifications",
+                "cmd_t": "homeassistant/notify/test-host/notifications/set",
+                "ent_cat": "config",
+                "json_attr_t": "homeassistant/notify/test-host/attributes",
+                "ent_pic": "https://example.com/bell.png",
+            })
+        );
+    }
+
+    #[test]
+    fn multiple_availability_topics_use_a_list_and_mode() {
+        let button = HomeAssistantComponent::button(
+            "Suspend".to_string(),
+            "test-host_suspend".to_string(),
+            "homeassistant/button/test-host/suspend/set".to_string(),
+        )
+        .with_availability(
+            vec![
+                Availability::new("homeassistant/device/test-host/availability".to_string()),
+                Availability::new("homeassistant/sensor/test-host/status/state".to_string())
+                    .with_template("{{ value_json.availability }}".to_string()),
+            ],
+            "all",
+        );
+
+        assert_eq!(
+            to_json(&button),
+            json!({
+                "p": "button",
+                "name": "Suspend",
+                "unique_id": "test-host_suspend",
+                "cmd_t": "homeassistant/button/test-host/suspend/set",
+                "avty": [
+                    { "t": "homeassistant/device/test-host/availability" },
+                    {
+                        "t": "homeassistant/sensor/test-host/status/state",
+                        "val_tpl": "{{ value_json.availability }}",
+                    },
+                ],
+                "avty_mode": "all",
+            })
+        );
+    }
+
+    #[test]
+    fn custom_availability_payloads_are_declared() {
+        let single = HomeAssistantComponent::button(
+            "Suspend".to_string(),
+            "test-host_suspend".to_string(),
+            "homeassistant/button/test-host/suspend/set".to_string(),
+        )
+        .with_availability(
+            vec![
+                Availability::new("homeassistant/device/test-host/availability".to_string())
+                    .with_payloads("up".to_string(), "down".to_string()),
+            ],
+            "all",
+        );
+        let json = to_json(&single);
+        assert_eq!(json["pl_avail"], "up");
+        assert_eq!(json["pl_not_avail"], "down");
+
+        let multiple = single.with_availability(
+            vec![
+                Availability::new("homeassistant/device/test-host/availability".to_string())
+                    .with_payloads("up".to_string(), "down".to_string()),
+                Availability::new("homeassistant/device/test-host/dbus_availability".to_string())
+                    .with_payloads("up".to_string(), "down".to_string()),
+            ],
+            "all",
+        );
+        let json = to_json(&multiple);
+        assert!(json.get("pl_avail").is_none());
+        assert_eq!(json["avty"][1]["pl_avail"], "up");
+        assert_eq!(json["avty"][1]["pl_not_avail"], "down");
+    }
+
+    #[test]
+    fn encoding_is_declared_only_when_set() {
+        let sensor = || {
+            HomeAssistantComponent::sensor(
+                "Snapshot".to_string(),
+                "test-host_snapshot".to_string(),
+                "homeassistant/sensor/test-host/snapshot/state".to_string(),
+                None,
+                None,
+                None,
+            )
+        };
+
+        assert!(to_json(&sensor()).get("e").is_none());
+        assert_eq!(
+            to_json(&sensor().with_encoding(Some(String::new())))["e"],
+            ""
+        );
+        assert_eq!(
+            to_json(&sensor().with_encoding(Some("latin-1".to_string())))["e"],
+            "latin-1"
+        );
+    }
+
+    #[test]
+    fn colliding_component_ids_are_rejected() {
+        let button = HomeAssistantComponent::button(
+            "Reboot".to_string(),
+            "test-host_reboot".to_string(),
+            "homeassistant/button/test-host/reboot/set".to_string(),
+        );
+        let switch = HomeAssistantComponent::switch(
+            "reboot".to_string(),
+            "test-host_reboot".to_string(),
+            "homeassistant/switch/test-host/reboot/set".to_string(),
+            "homeassistant/switch/test-host/reboot/state".to_string(),
+            false,
+        );
+        let other = HomeAssistantComponent::button(
+            "Suspend".to_string(),
+            "test-host_suspend".to_string(),
+            "homeassistant/button/test-host/suspend/set".to_string(),
+        );
+
+        let unique = vec![
+            ("test-host_reboot".to_string(), button.clone()),
+            ("test-host_suspend".to_string(), other),
+        ];
+        assert!(check_unique_component_ids(&unique).is_ok());
+
+        let colliding = vec![
+            ("test-host_reboot".to_string(), button),
+            ("test-host_reboot".to_string(), switch),
+        ];
+        let error = check_unique_component_ids(&colliding)
+            .expect_err("colliding ids are rejected")
+            .to_string();
+        assert!(error.contains("button 'Reboot'"), "{}", error);
+        assert!(error.contains("switch 'reboot'"), "{}", error);
+        assert!(error.contains("test-host_reboot"), "{}", error);
+    }
+
+    #[test]
+    fn large_discovery_falls_back_to_one_config_per_component() {
+        let components = || {
+            vec![(
+                "test-host_suspend".to_string(),
+                HomeAssistantComponent::button(
+                    "Suspend".to_string(),
+                    "test-host_suspend".to_string(),
+                    "homeassistant/button/test-host/suspend/set".to_string(),
+                ),
+            )]
+        };
+
+        let device =
+            build_discovery_messages(&sample_config(), components()).expect("discovery builds");
+        assert!(!device.per_component);
+        assert!(device.contains_topic("homeassistant/device/test-host/config"));
+
+        let config = Config::from_toml(&format!(
+            "{}\ndiscovery_max_payload_bytes = 100",
+            SAMPLE_CONFIG
+        ))
+        .expect("config is valid");
+        let split = build_discovery_messages(&config, components()).expect("discovery builds");
+        assert!(split.per_component);
+        assert_eq!(split.messages.len(), 1);
+        let (topic, payload) = &split.messages[0];
+        assert_eq!(topic, "homeassistant/button/test-host_suspend/config");
+        let payload: serde_json::Value = serde_json::from_str(payload).expect("payload is JSON");
+        assert!(payload.get("p").is_none());
+        assert_eq!(payload["unique_id"], "test-host_suspend");
+        assert_eq!(payload["dev"]["ids"], "test-host");
+    }
+
+    #[test]
+    fn minimal_discovery_still_contains_the_status_sensor() {
+        let config = Config::from_toml(&format!("{}\nsystem_monitoring = false", SAMPLE_CONFIG))
+            .expect("config is valid");
+        assert!(build_discovery_messages(&config, Vec::new()).is_err());
+
+        let discovery =
+            build_discovery_messages(&config, vec![crate::dbus::create_status_component(&config)])
+                .expect("discovery builds");
+        assert_eq!(discovery.messages.len(), 1);
+        let (topic, payload) = &discovery.messages[0];
+        assert_eq!(topic, "homeassistant/device/test-host/config");
+        let payload: serde_json::Value = serde_json::from_str(payload).expect("payload is JSON");
+        assert_eq!(payload["cmps"]["test-host_status"]["p"], "sensor");
+    }
+}
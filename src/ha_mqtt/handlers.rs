@@ -1,33 +1,222 @@
+use super::MqttClient;
+use crate::components::buttons::{CommandContext, DEFAULT_MAX_COMMAND_OUTPUT, Placeholders};
+use crate::components::confirmation::Confirmation;
+use crate::components::last_command::LastCommandReporter;
+use crate::components::notifications::{NotificationDefaults, NotificationDelivery};
+use crate::error::{Error, Result};
+use crate::utils::ExecMode;
 use crate::utils::config::DBusAction;
-use rumqttc::{AsyncClient, QoS};
+use rumqttc::QoS;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, Notify, Semaphore, watch};
+
+/// Commands run at the same time unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_COMMANDS: usize = 4;
 
 #[derive(Debug, Clone)]
 pub enum SwitchAction {
-    Exec(String),
+    Exec(String, ExecMode, CommandContext),
     DBus(DBusAction),
 }
 
+/// Check that `+` and `#` occupy whole topic levels and `#` is last,
+/// as required by the MQTT spec
+fn validate_topic_filter(filter: &str) -> Result<()> {
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (index, level) in levels.iter().enumerate() {
+        let misplaced_plus = level.contains('+') && *level != "+";
+        let misplaced_hash = level.contains('#') && (*level != "#" || index != levels.len() - 1);
+        if misplaced_plus || misplaced_hash {
+            return Err(Error::Config(format!(
+                "Invalid topic filter '{}': wildcards must occupy a whole level and '#' must be last",
+                filter
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Match a topic against an MQTT filter, returning the levels captured by
+/// the wildcards joined with `/`, or `None` if the topic does not match
+fn match_topic_filter(filter: &str, topic: &str) -> Option<String> {
+    // Wildcards at the first level never match topics starting with '$'
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return None;
+    }
+
+    let mut captured = Vec::new();
+    let mut topic_levels = topic.split('/');
+    for filter_level in filter.split('/') {
+        match filter_level {
+            // '#' also matches the parent level, e.g. "a/#" matches "a"
+            "#" => {
+                captured.extend(topic_levels);
+                return Some(captured.join("/"));
+            }
+            "+" => captured.push(topic_levels.next()?),
+            literal => {
+                if topic_levels.next()? != literal {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if topic_levels.next().is_some() {
+        return None;
+    }
+    Some(captured.join("/"))
+}
+
+/// Orders the commands of one switch, whose tasks may start in any order:
+/// a command is skipped once a newer one has run, so the last published
+/// state always belongs to the last command received
+#[derive(Debug, Default)]
+pub struct CommandSequence {
+    /// Number of the most recently received command
+    received: AtomicU64,
+    /// Number of the most recently executed command; held while one runs
+    executed: Mutex<u64>,
+}
+
+/// Number of registered handlers by kind, for self-diagnostics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HandlerCounts {
+    /// Subscribed topics, one per handler
+    pub subscriptions: usize,
+    /// Commands that can be triggered: buttons, button group entries, switches
+    /// and notify entities, plus the built-in handlers
+    pub handlers: usize,
+    pub buttons: usize,
+    pub button_group_commands: usize,
+    pub switches: usize,
+    pub notifications: usize,
+    /// Refresh, restart, reload, log publishing and session probe
+    pub builtin: usize,
+}
+
 /// Unified topic management for all component types
 #[derive(Debug, Clone)]
 pub enum TopicHandler {
     Button {
         topic: String,
+        /// Entity name, reported as the last command
+        name: String,
         exec_command: String,
+        exec_mode: ExecMode,
+        context: CommandContext,
+        /// Skip retained messages replayed by the broker
+        ignore_retained: bool,
     },
     Switch {
         command_topic: String,
+        /// Entity name, reported as the last command
+        name: String,
         state_topic: String,
         action: SwitchAction,
+        retain_state: bool,
+        /// Skip retained messages replayed by the broker
+        ignore_retained: bool,
+        sequence: Arc<CommandSequence>,
     },
     Notification {
         topic: String,
+        defaults: NotificationDefaults,
+    },
+    Refresh {
+        topic: String,
+    },
+    Restart {
+        topic: String,
+    },
+    Reload {
+        topic: String,
+    },
+    PublishLogs {
+        topic: String,
+    },
+    /// Echo of the message published to verify a resumed session
+    SessionProbe {
+        topic: String,
+    },
+    /// Buttons sharing one wildcard subscription, keyed by matched entity id
+    ButtonGroup {
+        filter: String,
+        commands: HashMap<String, String>,
+        exec_mode: ExecMode,
+        /// Skip retained messages replayed by the broker
+        ignore_retained: bool,
     },
 }
 
-/// Container for all topics that need to be handled
-#[derive(Debug, Default)]
+impl TopicHandler {
+    /// Whether retained messages on this handler's topic are ignored
+    fn ignores_retained(&self) -> bool {
+        match self {
+            TopicHandler::Button {
+                ignore_retained, ..
+            }
+            | TopicHandler::Switch {
+                ignore_retained, ..
+            }
+            | TopicHandler::ButtonGroup {
+                ignore_retained, ..
+            } => *ignore_retained,
+            _ => false,
+        }
+    }
+}
+
+/// The fields of a switch handler needed to run one of its commands
+struct SwitchTarget<'a> {
+    name: &'a str,
+    state_topic: &'a str,
+    action: &'a SwitchAction,
+    retain_state: bool,
+    sequence: &'a Arc<CommandSequence>,
+}
+
+/// Container for all topics that need to be handled, keyed by subscribed topic
+#[derive(Debug)]
 pub struct TopicHandlers {
-    pub handlers: Vec<TopicHandler>,
+    pub handlers: HashMap<String, TopicHandler>,
+    /// Binaries that may be executed; `None` allows every command
+    pub allowed_commands: Option<Vec<String>>,
+    /// Wakes the system monitor for an immediate metrics publish
+    pub refresh_trigger: Arc<Notify>,
+    /// Tells the main loop to shut down gracefully and restart the daemon
+    pub restart_trigger: Arc<Notify>,
+    /// Tells the main loop to reload the configuration
+    pub reload_trigger: Arc<Notify>,
+    /// Tells the main loop that the session probe came back
+    pub session_probe_echo: Arc<Notify>,
+    /// Set by the system monitor while metrics publishing keeps failing
+    pub metrics_degraded: watch::Sender<bool>,
+    /// Set by the system monitor after every update, so the main loop can
+    /// tell a wedged monitor from an idle one
+    pub monitor_heartbeat: watch::Sender<tokio::time::Instant>,
+    /// Bytes of stdout and stderr kept from each executed command
+    pub max_command_output: usize,
+    /// Bounds the button and switch commands running at the same time
+    pub command_slots: Arc<Semaphore>,
+    /// Retries and queueing for notifications received on notify topics
+    pub notification_delivery: NotificationDelivery,
+    /// Publishes the result of every executed button and switch command
+    pub last_command: Option<LastCommandReporter>,
+    /// Buttons that need a second press to run, keyed by command topic
+    pub confirmations: HashMap<String, Arc<Confirmation>>,
+    /// Substituted for `{hostname}` in commands
+    pub hostname: String,
+}
+
+impl Default for TopicHandlers {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TopicHandlers {
@@ -38,148 +227,536 @@ impl TopicHandlers {
     ///
     /// # Examples
     /// ```
+    /// use hars_imp::ha_mqtt::TopicHandlers;
+    ///
     /// let handlers = TopicHandlers::new();
     /// ```
     pub fn new() -> Self {
         Self {
-            handlers: Vec::new(),
+            handlers: HashMap::new(),
+            allowed_commands: None,
+            refresh_trigger: Arc::new(Notify::new()),
+            restart_trigger: Arc::new(Notify::new()),
+            reload_trigger: Arc::new(Notify::new()),
+            session_probe_echo: Arc::new(Notify::new()),
+            metrics_degraded: watch::Sender::new(false),
+            monitor_heartbeat: watch::Sender::new(tokio::time::Instant::now()),
+            max_command_output: DEFAULT_MAX_COMMAND_OUTPUT,
+            command_slots: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_COMMANDS)),
+            notification_delivery: NotificationDelivery::default(),
+            last_command: None,
+            confirmations: HashMap::new(),
+            hostname: String::new(),
+        }
+    }
+
+    /// Run at most this many button and switch commands at the same time
+    pub fn set_max_concurrent_commands(&mut self, max_concurrent_commands: usize) {
+        self.command_slots = Arc::new(Semaphore::new(max_concurrent_commands));
+    }
+
+    /// Retry and queue notifications the notification service does not accept
+    pub fn set_notification_delivery(&mut self, notification_delivery: NotificationDelivery) {
+        self.notification_delivery = notification_delivery;
+    }
+
+    /// Report every executed button and switch command to `reporter`
+    pub fn set_last_command_reporter(&mut self, reporter: LastCommandReporter) {
+        self.last_command = Some(reporter);
+    }
+
+    /// Require a second press on `topic` before its button runs
+    pub fn add_confirmation(&mut self, topic: String, confirmation: Confirmation) {
+        self.confirmations.insert(topic, Arc::new(confirmation));
+    }
+
+    /// Every confirmable button's arming state, e.g. to publish them as
+    /// disarmed after startup
+    pub fn confirmations(&self) -> Vec<Arc<Confirmation>> {
+        self.confirmations.values().cloned().collect()
+    }
+
+    /// Cap the stdout and stderr captured from each command, in bytes
+    pub fn set_max_command_output(&mut self, max_command_output: usize) {
+        self.max_command_output = max_command_output;
+    }
+
+    /// Set the hostname substituted for `{hostname}` in commands
+    pub fn set_hostname(&mut self, hostname: String) {
+        self.hostname = hostname;
+    }
+
+    /// Restrict command execution to the given binaries
+    pub fn set_allowed_commands(&mut self, allowed_commands: Option<Vec<String>>) {
+        self.allowed_commands = allowed_commands;
+    }
+
+    /// Returns true if the command may run under the configured allowlist,
+    /// logging a security warning otherwise
+    fn check_command_allowed(&self, command: &str) -> bool {
+        use crate::components::buttons::is_command_allowed;
+        use tracing::warn;
+
+        match &self.allowed_commands {
+            Some(allowed) if !is_command_allowed(command, allowed) => {
+                warn!(
+                    "Security: refusing to execute '{}' as its binary is not in allowed_commands",
+                    command
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Register a handler for a topic, rejecting topics that are already claimed
+    fn register(&mut self, topic: String, handler: TopicHandler) -> Result<()> {
+        match self.handlers.entry(topic) {
+            Entry::Occupied(entry) => Err(Error::Config(format!(
+                "Topic '{}' is claimed by more than one entity; check for duplicate names",
+                entry.key()
+            ))),
+            Entry::Vacant(entry) => {
+                entry.insert(handler);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn add_button(
+        &mut self,
+        topic: String,
+        name: String,
+        exec_command: String,
+        exec_mode: ExecMode,
+        context: CommandContext,
+        ignore_retained: bool,
+    ) -> Result<()> {
+        self.register(
+            topic.clone(),
+            TopicHandler::Button {
+                topic,
+                name,
+                exec_command,
+                exec_mode,
+                context,
+                ignore_retained,
+            },
+        )
+    }
+
+    pub fn add_switch(
+        &mut self,
+        command_topic: String,
+        name: String,
+        state_topic: String,
+        action: SwitchAction,
+        retain_state: bool,
+        ignore_retained: bool,
+    ) -> Result<()> {
+        self.register(
+            command_topic.clone(),
+            TopicHandler::Switch {
+                command_topic,
+                name,
+                state_topic,
+                action,
+                retain_state,
+                ignore_retained,
+                sequence: Arc::default(),
+            },
+        )
+    }
+
+    pub fn add_notification(
+        &mut self,
+        topic: String,
+        defaults: NotificationDefaults,
+    ) -> Result<()> {
+        self.register(
+            topic.clone(),
+            TopicHandler::Notification { topic, defaults },
+        )
+    }
+
+    pub fn add_refresh(&mut self, topic: String) -> Result<()> {
+        self.register(topic.clone(), TopicHandler::Refresh { topic })
+    }
+
+    pub fn add_restart(&mut self, topic: String) -> Result<()> {
+        self.register(topic.clone(), TopicHandler::Restart { topic })
+    }
+
+    pub fn add_reload(&mut self, topic: String) -> Result<()> {
+        self.register(topic.clone(), TopicHandler::Reload { topic })
+    }
+
+    pub fn add_publish_logs(&mut self, topic: String) -> Result<()> {
+        self.register(topic.clone(), TopicHandler::PublishLogs { topic })
+    }
+
+    pub fn add_session_probe(&mut self, topic: String) -> Result<()> {
+        self.register(topic.clone(), TopicHandler::SessionProbe { topic })
+    }
+
+    /// Register a wildcard subscription that dispatches to `commands` by the
+    /// entity id captured from the concrete topic
+    pub fn add_button_group(
+        &mut self,
+        filter: String,
+        commands: HashMap<String, String>,
+        exec_mode: ExecMode,
+        ignore_retained: bool,
+    ) -> Result<()> {
+        validate_topic_filter(&filter)?;
+        self.register(
+            filter.clone(),
+            TopicHandler::ButtonGroup {
+                filter,
+                commands,
+                exec_mode,
+                ignore_retained,
+            },
+        )
+    }
+
+    /// Find the handler for a topic, trying exact subscriptions before wildcard filters
+    fn find_handler(&self, topic: &str) -> Option<&TopicHandler> {
+        self.handlers.get(topic).or_else(|| {
+            self.handlers.values().find(|handler| {
+                matches!(handler, TopicHandler::ButtonGroup { filter, .. }
+                    if match_topic_filter(filter, topic).is_some())
+            })
+        })
+    }
+
+    /// Wait for a free command slot, logging if all of them are taken
+    async fn acquire_command_slot(slots: &Semaphore) -> Option<tokio::sync::SemaphorePermit<'_>> {
+        if slots.available_permits() == 0 {
+            tracing::debug!("All command slots busy, waiting for a running command to finish");
         }
+        // The semaphore is never closed
+        slots.acquire().await.ok()
     }
 
-    pub fn add_button(&mut self, topic: String, exec_command: String) {
-        self.handlers.push(TopicHandler::Button {
-            topic,
-            exec_command,
+    /// Run a button command in the background after checking it against the allowlist
+    fn press_button(
+        &self,
+        topic: &str,
+        name: &str,
+        exec_command: &str,
+        exec_mode: ExecMode,
+        context: &CommandContext,
+    ) {
+        use crate::components::buttons::execute_command;
+        use tracing::{error, info};
+
+        if !self.check_command_allowed(exec_command) {
+            return;
+        }
+        info!(
+            "Button press detected on topic '{}', executing: {}",
+            topic, exec_command
+        );
+        let placeholders = Placeholders::new(&self.hostname, "PRESS");
+        let name = name.to_string();
+        let exec_command = exec_command.to_string();
+        let context = context.clone();
+        let max_output = self.max_command_output;
+        let slots = Arc::clone(&self.command_slots);
+        let last_command = self.last_command.clone();
+        tokio::spawn(async move {
+            let Some(slot) = Self::acquire_command_slot(&slots).await else {
+                return;
+            };
+            let result = execute_command(
+                &exec_command,
+                exec_mode,
+                &context,
+                &placeholders,
+                max_output,
+            )
+            .await;
+            drop(slot);
+            match &result {
+                Ok(output) => {
+                    info!("Command executed successfully: {}", output);
+                }
+                Err(e) => {
+                    error!("Failed to execute command '{}': {}", exec_command, e);
+                }
+            }
+            if let Some(last_command) = last_command {
+                last_command.report(&name, "button", None, &result).await;
+            }
         });
     }
 
-    pub fn add_switch(&mut self, command_topic: String, state_topic: String, action: SwitchAction) {
-        self.handlers.push(TopicHandler::Switch {
-            command_topic,
+    /// Run a switch action in the background and publish the resulting state,
+    /// or an empty state if it fails
+    fn toggle_switch(&self, switch: SwitchTarget<'_>, payload: &str, client: &MqttClient) {
+        use crate::components::switch::{execute_dbus_switch_command, execute_switch_command};
+        use tracing::{debug, error, info};
+
+        let SwitchTarget {
+            name,
             state_topic,
             action,
-        });
-    }
+            retain_state,
+            sequence,
+        } = switch;
+        let number = sequence.received.fetch_add(1, Ordering::AcqRel) + 1;
+        let placeholders = Placeholders::new(&self.hostname, payload);
+        let name = name.to_string();
+        let state_topic = state_topic.to_string();
+        let action = action.clone();
+        let sequence = Arc::clone(sequence);
+        let payload = payload.to_string();
+        let client = client.clone();
+        let max_output = self.max_command_output;
+        let slots = Arc::clone(&self.command_slots);
+        let last_command = self.last_command.clone();
+        tokio::spawn(async move {
+            let mut executed = sequence.executed.lock().await;
+            if *executed > number {
+                debug!(
+                    "Skipping switch command '{}' for '{}', a newer command already ran",
+                    payload, state_topic
+                );
+                return;
+            }
+            let Some(slot) = Self::acquire_command_slot(&slots).await else {
+                return;
+            };
 
-    pub fn add_notification(&mut self, topic: String) {
-        self.handlers.push(TopicHandler::Notification { topic });
+            let execution_result = match &action {
+                SwitchAction::Exec(exec_command, exec_mode, context) => {
+                    execute_switch_command(
+                        exec_command,
+                        &payload.to_lowercase(),
+                        *exec_mode,
+                        context,
+                        &placeholders,
+                        max_output,
+                    )
+                    .await
+                }
+                SwitchAction::DBus(dbus_action) => {
+                    execute_dbus_switch_command(dbus_action, payload == "ON").await
+                }
+            };
+            drop(slot);
+            *executed = number;
+
+            // Published while still holding the sequence lock, so the states
+            // of this switch go out in the order its commands ran
+            let state = match &execution_result {
+                Ok(_output) => {
+                    info!("Switch command executed successfully");
+                    payload.as_str()
+                }
+                Err(e) => {
+                    error!("Failed to execute switch command: {}", e);
+                    // An empty payload indicates the command failure
+                    ""
+                }
+            };
+            match client
+                .publish(&state_topic, QoS::AtLeastOnce, retain_state, state)
+                .await
+            {
+                Ok(()) => debug!(
+                    "Published switch state '{}' to topic '{}'",
+                    state, state_topic
+                ),
+                Err(e) => error!("Failed to publish switch state to '{}': {}", state_topic, e),
+            }
+            if let Some(last_command) = last_command {
+                last_command
+                    .report(&name, "switch", Some(&payload), &execution_result)
+                    .await;
+            }
+        });
     }
 
-    /// Handle an incoming MQTT message and return true if handled
+    /// Handle an incoming MQTT message and return true if handled.
+    /// `retained` marks a message the broker replayed from its retained store.
     pub async fn handle_message(
         &self,
         topic: &str,
         payload: &str,
-        client: &AsyncClient,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        use crate::components::buttons::execute_command;
-        use crate::components::switch::{execute_dbus_switch_command, execute_switch_command};
-        use tracing::{debug, error, info};
+        retained: bool,
+        client: &MqttClient,
+    ) -> Result<bool> {
+        use tracing::{debug, info, warn};
 
-        for handler in &self.handlers {
-            match handler {
-                TopicHandler::Button {
-                    topic: button_topic,
-                    exec_command,
-                } => {
-                    if topic == button_topic && payload.trim() == "PRESS" {
-                        info!(
-                            "Button press detected on topic '{}', executing: {}",
-                            topic, exec_command
-                        );
-                        match execute_command(exec_command).await {
-                            Ok(output) => {
-                                info!("Command executed successfully: {}", output);
-                            }
-                            Err(e) => {
-                                error!("Failed to execute command '{}': {}", exec_command, e);
-                            }
-                        }
+        let Some(handler) = self.find_handler(topic) else {
+            return Ok(false);
+        };
+
+        if retained && handler.ignores_retained() {
+            info!(
+                "Ignoring retained command on topic '{}': {}",
+                topic,
+                payload.trim()
+            );
+            return Ok(true);
+        }
+
+        // The first press of a button needing confirmation only arms it
+        if payload.trim() == "PRESS"
+            && let Some(confirmation) = self.confirmations.get(topic)
+            && !confirmation.press(client)
+        {
+            return Ok(true);
+        }
+
+        match handler {
+            TopicHandler::Button {
+                name,
+                exec_command,
+                exec_mode,
+                context,
+                ..
+            } => {
+                if payload.trim() == "PRESS" {
+                    self.press_button(topic, name, exec_command, *exec_mode, context);
+                    return Ok(true);
+                }
+            }
+            TopicHandler::Switch {
+                name,
+                state_topic,
+                action,
+                retain_state,
+                sequence,
+                ..
+            } => {
+                let payload = payload.trim();
+                if payload == "ON" || payload == "OFF" {
+                    if let SwitchAction::Exec(exec_command, ..) = action
+                        && !self.check_command_allowed(exec_command)
+                    {
                         return Ok(true);
                     }
+                    info!(
+                        "Switch command received on topic '{}': {}, executing action",
+                        topic, payload
+                    );
+
+                    let switch = SwitchTarget {
+                        name,
+                        state_topic,
+                        action,
+                        retain_state: *retain_state,
+                        sequence,
+                    };
+                    self.toggle_switch(switch, payload, client);
+                    return Ok(true);
+                } else {
+                    debug!(
+                        "Ignoring invalid switch payload '{}' on topic '{}'",
+                        payload, topic
+                    );
                 }
-                TopicHandler::Switch {
-                    command_topic,
-                    state_topic,
-                    action,
-                } => {
-                    if topic == command_topic {
-                        let payload = payload.trim();
-                        if payload == "ON" || payload == "OFF" {
-                            let switch_state = payload == "ON";
-                            info!(
-                                "Switch command received on topic '{}': {}, executing action",
-                                topic, payload
-                            );
+            }
+            TopicHandler::Notification {
+                topic: notification_topic,
+                defaults,
+            } => {
+                debug!(
+                    "Processing notification command on topic '{}': {}",
+                    topic, payload
+                );
 
-                            let execution_result = match action {
-                                SwitchAction::Exec(exec_command) => {
-                                    execute_switch_command(exec_command, &payload.to_lowercase())
-                                        .await
-                                }
-                                SwitchAction::DBus(dbus_action) => {
-                                    execute_dbus_switch_command(dbus_action, switch_state).await
-                                }
-                            };
-
-                            match execution_result {
-                                Ok(_output) => {
-                                    info!("Switch command executed successfully");
-                                    // Publish the new state to the state topic
-                                    client
-                                        .publish(state_topic, QoS::AtLeastOnce, true, payload)
-                                        .await?;
-                                    debug!(
-                                        "Published switch state '{}' to topic '{}'",
-                                        payload, state_topic
-                                    );
-                                }
-                                Err(e) => {
-                                    error!("Failed to execute switch command: {}", e);
-                                    // Publish empty payload to indicate command failure
-                                    client
-                                        .publish(state_topic, QoS::AtLeastOnce, true, "")
-                                        .await?;
-                                    debug!(
-                                        "Published empty state to topic '{}' due to command failure",
-                                        state_topic
-                                    );
-                                }
-                            }
-                            return Ok(true);
-                        } else {
-                            debug!(
-                                "Ignoring invalid switch payload '{}' on topic '{}'",
-                                payload, topic
-                            );
-                        }
+                // Use the notification handler from the notifications module
+                use crate::components::notifications::handle_notification_command;
+
+                // Delivery may be retried with backoff, so it runs in the
+                // background instead of holding up the event loop
+                let topic = topic.to_string();
+                let payload = payload.to_string();
+                let notification_topic = notification_topic.clone();
+                let defaults = defaults.clone();
+                let delivery = self.notification_delivery.clone();
+                tokio::spawn(async move {
+                    if handle_notification_command(
+                        &topic,
+                        &payload,
+                        &notification_topic,
+                        &defaults,
+                        &delivery,
+                    )
+                    .await
+                    {
+                        info!("Notification processed successfully");
+                    } else {
+                        // This shouldn't happen since we already matched the topic,
+                        // but handle it gracefully
+                        debug!("Notification handler returned false for matched topic");
                     }
+                });
+                return Ok(true);
+            }
+            TopicHandler::Refresh { .. } => {
+                if payload.trim() == "PRESS" {
+                    info!("Metrics refresh requested on topic '{}'", topic);
+                    self.refresh_trigger.notify_one();
+                    return Ok(true);
+                }
+            }
+            TopicHandler::Restart { .. } => {
+                if payload.trim() == "PRESS" {
+                    warn!("Daemon restart requested on topic '{}'", topic);
+                    self.restart_trigger.notify_one();
+                    return Ok(true);
                 }
-                TopicHandler::Notification {
-                    topic: notification_topic,
-                } => {
-                    if topic == notification_topic {
-                        debug!(
-                            "Processing notification command on topic '{}': {}",
-                            topic, payload
-                        );
-
-                        // Use the notification handler from the notifications module
-                        use crate::components::notifications::handle_notification_command;
-
-                        match handle_notification_command(topic, payload, notification_topic).await
-                        {
-                            true => {
-                                info!("Notification processed successfully");
-                                return Ok(true);
-                            }
-                            false => {
-                                // This shouldn't happen since we already matched the topic,
-                                // but handle it gracefully
-                                debug!("Notification handler returned false for matched topic");
-                            }
+            }
+            TopicHandler::Reload { .. } => {
+                if payload.trim() == "PRESS" {
+                    info!("Config reload requested on topic '{}'", topic);
+                    self.reload_trigger.notify_one();
+                    return Ok(true);
+                }
+            }
+            TopicHandler::PublishLogs { .. } => {
+                if payload.trim() == "PRESS" {
+                    info!("Log publish requested on topic '{}'", topic);
+                    crate::utils::logging::request_log_publish();
+                    return Ok(true);
+                }
+            }
+            TopicHandler::SessionProbe { .. } => {
+                debug!("Session probe received on topic '{}'", topic);
+                self.session_probe_echo.notify_one();
+                return Ok(true);
+            }
+            TopicHandler::ButtonGroup {
+                filter,
+                commands,
+                exec_mode,
+                ..
+            } => {
+                if payload.trim() == "PRESS" {
+                    let entity_id = match_topic_filter(filter, topic).unwrap_or_default();
+                    match commands.get(&entity_id) {
+                        Some(exec_command) => {
+                            self.press_button(
+                                topic,
+                                &entity_id,
+                                exec_command,
+                                *exec_mode,
+                                &CommandContext::default(),
+                            );
+                        }
+                        None => {
+                            debug!(
+                                "No command for entity '{}' in button group '{}'",
+                                entity_id, filter
+                            );
                         }
                     }
+                    return Ok(true);
                 }
             }
         }
@@ -188,20 +765,35 @@ impl TopicHandlers {
 
     /// Get all topics that need to be subscribed to
     pub fn get_subscription_topics(&self) -> Vec<String> {
-        let mut topics = Vec::new();
-        for handler in &self.handlers {
+        self.handlers.keys().cloned().collect()
+    }
+
+    /// Count the registered handlers by kind
+    pub fn counts(&self) -> HandlerCounts {
+        let mut counts = HandlerCounts {
+            subscriptions: self.handlers.len(),
+            ..HandlerCounts::default()
+        };
+        for handler in self.handlers.values() {
             match handler {
-                TopicHandler::Button { topic, .. } => {
-                    topics.push(topic.clone());
-                }
-                TopicHandler::Switch { command_topic, .. } => {
-                    topics.push(command_topic.clone());
-                }
-                TopicHandler::Notification { topic, .. } => {
-                    topics.push(topic.clone());
+                TopicHandler::Button { .. } => counts.buttons += 1,
+                TopicHandler::ButtonGroup { commands, .. } => {
+                    counts.button_group_commands += commands.len()
                 }
+                TopicHandler::Switch { .. } => counts.switches += 1,
+                TopicHandler::Notification { .. } => counts.notifications += 1,
+                TopicHandler::Refresh { .. }
+                | TopicHandler::Restart { .. }
+                | TopicHandler::Reload { .. }
+                | TopicHandler::PublishLogs { .. }
+                | TopicHandler::SessionProbe { .. } => counts.builtin += 1,
             }
         }
-        topics
+        counts.handlers = counts.buttons
+            + counts.button_group_commands
+            + counts.switches
+            + counts.notifications
+            + counts.builtin;
+        counts
     }
 }
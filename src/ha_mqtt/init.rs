@@ -1,113 +1,485 @@
-use rumqttc::{AsyncClient, MqttOptions};
+use rumqttc::QoS;
 use std::time::Duration;
 use tokio::time;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::components::{
-    SystemMonitor, create_button_components_and_setup, create_notification_components_and_setup,
+    Confirmation, LastCommandReporter, NotificationDelivery, PsiMonitor, SystemMonitor,
+    availability_probes, create_button_components_and_setup, create_handler_counts_component,
+    create_health_component, create_last_command_component, create_logs_components_and_setup,
+    create_notification_components_and_setup, create_publish_failures_component,
+    create_refresh_button_component_and_setup, create_reload_button_component_and_setup,
+    create_restart_button_component_and_setup, create_smart_components,
     create_switch_components_and_setup, create_system_sensor_components,
+    handler_counts_state_topic, initial_switch_states, metric_availability_topics,
+    metric_component_ids, notification_availability_topic, publish_handler_counts,
+    publish_initial_switch_states, smart_devices,
 };
-use crate::dbus::{StatusManager, create_status_component};
+use crate::dbus::{
+    AvailabilityPayloads, DaemonState, StatusManager, create_status_component, status_state_topic,
+};
+use crate::error::{Error, Result};
 use crate::utils::Config;
 
-use super::{TopicHandlers, publish_unified_discovery};
+use super::{
+    Availability, MqttClient, MqttEventLoop, TopicHandlers, birth_message,
+    build_discovery_messages, check_unique_component_ids, clear_legacy_discovery,
+    component_discovery_topic, legacy_discovery_topic, publish_birth_message,
+    publish_discovery_messages, session::session_probe_topic,
+};
+
+/// Initial delay between attempts to publish the startup status
+const INITIAL_STATUS_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Create a system monitor and start its monitoring loop in the background
+pub fn spawn_system_monitor(
+    config: &Config,
+    client: &MqttClient,
+    topic_handlers: &TopicHandlers,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if !config.system_monitoring {
+        debug!("System monitoring is disabled");
+        return None;
+    }
+
+    info!("Starting system monitor");
+    let mut system_monitor = SystemMonitor::from_config(config, client.clone())
+        .with_refresh_trigger(topic_handlers.refresh_trigger.clone())
+        .with_degraded_signal(topic_handlers.metrics_degraded.clone())
+        .with_heartbeat(topic_handlers.monitor_heartbeat.clone());
+    // Count the new monitor as alive until its first update
+    topic_handlers
+        .monitor_heartbeat
+        .send_replace(time::Instant::now());
+
+    Some(tokio::spawn(async move {
+        system_monitor.run_monitoring_loop().await;
+    }))
+}
+
+/// Poll the event loop until the broker acknowledges the first connection.
+/// Connection errors are retried every `update_interval_ms`; once
+/// `connect_timeout_ms` has passed, either give up (`fail_fast`) or keep
+/// retrying indefinitely.
+async fn wait_for_conn_ack(eventloop: &mut MqttEventLoop, config: &Config) -> Result<()> {
+    let retry_delay = Duration::from_millis(config.update_interval_ms);
+    let wait = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(event) if event.is_conn_ack() => return,
+                Ok(event) => debug!("MQTT event while connecting: {:?}", event),
+                Err(e) => {
+                    debug!(
+                        "Connection attempt failed: {}. Retrying in {}ms",
+                        e, config.update_interval_ms
+                    );
+                    time::sleep(retry_delay).await;
+                }
+            }
+        }
+    };
+    tokio::pin!(wait);
+
+    let timeout = Duration::from_millis(config.connect_timeout_ms);
+    if time::timeout(timeout, &mut wait).await.is_ok() {
+        info!("Connected to MQTT broker");
+        return Ok(());
+    }
+
+    error!(
+        "Could not connect to MQTT broker {}:{} within {}ms, check mqtt_url and mqtt_port",
+        config.mqtt_url, config.broker_port, config.connect_timeout_ms
+    );
+    if config.fail_fast {
+        return Err(Error::Timeout(format!(
+            "connecting to MQTT broker {}:{}",
+            config.mqtt_url, config.broker_port
+        )));
+    }
+
+    warn!("fail_fast is disabled, continuing to retry the broker connection");
+    wait.await;
+    info!("Connected to MQTT broker");
+    Ok(())
+}
+
+/// Publish the initial 'On' status, retrying with a short backoff since
+/// transient failures right after connecting are common
+async fn publish_initial_status(status_manager: &mut StatusManager, max_attempts: u32) {
+    let max_attempts = max_attempts.max(1);
+    let mut delay = INITIAL_STATUS_RETRY_DELAY;
 
+    for attempt in 1..=max_attempts {
+        debug!(
+            "Publishing initial 'On' status (attempt {}/{})",
+            attempt, max_attempts
+        );
+        match status_manager.publish_state(DaemonState::Connected).await {
+            Ok(()) => {
+                debug!("Successfully published initial status");
+                return;
+            }
+            Err(e) if attempt < max_attempts => {
+                debug!(
+                    "Failed to publish initial status: {}. Retrying in {}ms",
+                    e,
+                    delay.as_millis()
+                );
+                time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to publish initial status after {} attempts: {}",
+                    max_attempts, e
+                );
+            }
+        }
+    }
+}
+
+/// Connect to the broker and set up every entity. Subscriptions, discovery and
+/// the startup statuses are published by the returned startup task once the
+/// caller polls the event loop.
 pub async fn initialize_mqtt_connection(
     config: &Config,
-) -> Result<
-    (
-        AsyncClient,
-        rumqttc::EventLoop,
-        TopicHandlers,
-        StatusManager,
-        tokio::task::JoinHandle<()>,
-    ),
-    Box<dyn std::error::Error>,
-> {
-    // Set up MQTT options
-    let mut mqttoptions = MqttOptions::new(&config.hostname, &config.mqtt_url, config.mqtt_port);
-    mqttoptions.set_credentials(&config.username, &config.password);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-
-    // Create MQTT client
+) -> Result<(
+    MqttClient,
+    MqttEventLoop,
+    TopicHandlers,
+    StatusManager,
+    Option<tokio::task::JoinHandle<()>>,
+    tokio::task::JoinHandle<()>,
+)> {
+    // Create MQTT client for the configured protocol version
     debug!("Creating MQTT client");
-    let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+    let (client, mut eventloop) = MqttClient::new(config)?;
     debug!("MQTT client created successfully");
 
+    wait_for_conn_ack(&mut eventloop, config).await?;
+
+    debug!("Creating status manager");
+    let status_manager = StatusManager::new(
+        config.hostname.clone(),
+        config.availability_topic.clone(),
+        client.clone(),
+    )
+    .with_dbus_availability_topic(
+        config
+            .dbus_availability
+            .then(|| config.dbus_availability_topic.clone()),
+    )
+    .with_status_labels(config.status_labels.clone())
+    .with_availability_payloads(AvailabilityPayloads::from_config(config));
+    let birth = match &config.birth_topic {
+        Some(topic) => Some((topic.clone(), birth_message(config)?)),
+        None => None,
+    };
+
     // Collect all components for unified discovery
     let mut all_components = Vec::new();
     let mut topic_handlers = TopicHandlers::new();
+    if let Some(allowed) = &config.allowed_commands {
+        info!(
+            "Command allowlist active with {} permitted binaries",
+            allowed.len()
+        );
+    }
+    topic_handlers.set_allowed_commands(config.allowed_commands.clone());
+    topic_handlers.set_max_command_output(config.max_command_output);
+    topic_handlers.set_max_concurrent_commands(config.max_concurrent_commands);
+    topic_handlers.set_notification_delivery(NotificationDelivery::from_config(config));
+    topic_handlers.set_last_command_reporter(LastCommandReporter::new(client.clone(), config));
+    all_components.push(create_last_command_component(config));
+    topic_handlers.set_hostname(config.hostname.clone());
 
     // Handle button components and subscriptions
-    let (button_components, button_topics) =
-        create_button_components_and_setup(&client, config).await?;
+    let (button_components, button_topics) = create_button_components_and_setup(config).await?;
     all_components.extend(button_components);
 
     // Add button topics to unified handlers
-    for (topic, exec_command) in button_topics {
-        topic_handlers.add_button(topic, exec_command);
+    for (topic, name, exec_command, exec_mode, context, ignore_retained, confirmation) in
+        button_topics
+    {
+        if let Some(confirmation) = confirmation {
+            topic_handlers.add_confirmation(topic.clone(), confirmation);
+        }
+        topic_handlers.add_button(
+            topic,
+            name,
+            exec_command,
+            exec_mode,
+            context,
+            ignore_retained,
+        )?;
+    }
+
+    // Handle wildcard button groups, validating each filter before it is subscribed
+    if let Some(groups) = &config.button_group {
+        for group in groups {
+            topic_handlers.add_button_group(
+                group.topic.clone(),
+                group.commands.clone(),
+                group.exec_mode.unwrap_or(config.exec_mode),
+                group
+                    .ignore_retained
+                    .unwrap_or(config.ignore_retained_commands),
+            )?;
+        }
     }
 
     // Handle switch components and subscriptions
-    let (switch_components, switch_topics) =
-        create_switch_components_and_setup(&client, config).await?;
+    let (switch_components, switch_topics) = create_switch_components_and_setup(config).await?;
     all_components.extend(switch_components);
 
     // Add switch topics to unified handlers
-    for (command_topic, state_topic, action) in switch_topics {
-        topic_handlers.add_switch(command_topic, state_topic, action);
+    for (command_topic, name, state_topic, action, retain_state, ignore_retained) in switch_topics {
+        topic_handlers.add_switch(
+            command_topic,
+            name,
+            state_topic,
+            action,
+            retain_state,
+            ignore_retained,
+        )?;
     }
 
     // Handle notification components and subscriptions
-    let (notification_components, notification_topic) =
-        create_notification_components_and_setup(&client, config).await?;
+    let (notification_components, notification_topics) =
+        create_notification_components_and_setup(config).await?;
     all_components.extend(notification_components);
 
     // Add notification topic to unified handlers
-    topic_handlers.add_notification(notification_topic);
+    for (notification_topic, defaults) in notification_topics {
+        topic_handlers.add_notification(notification_topic, defaults)?;
+    }
+
+    // Handle the built-in metrics refresh button
+    if config.system_monitoring {
+        let (refresh_component, refresh_topic) =
+            create_refresh_button_component_and_setup(config).await?;
+        all_components.push(refresh_component);
+        topic_handlers.add_refresh(refresh_topic)?;
+    }
 
-    // Create system monitoring sensor components
-    let system_components = create_system_sensor_components(config);
-    all_components.extend(system_components);
+    // Handle the built-in daemon restart button if enabled
+    if let Some(((restart_id, restart_component), restart_topic)) =
+        create_restart_button_component_and_setup(config).await?
+    {
+        if config.restart_button_confirm {
+            let confirmation = Confirmation::new(
+                restart_component.name.clone(),
+                restart_id.clone(),
+                Duration::from_secs(config.confirm_window_secs),
+            );
+            all_components.push(confirmation.component());
+            topic_handlers.add_confirmation(restart_topic.clone(), confirmation);
+        }
+        all_components.push((restart_id, restart_component));
+        topic_handlers.add_restart(restart_topic)?;
+    }
+
+    // Handle the built-in config reload button if enabled
+    if let Some((reload_component, reload_topic)) =
+        create_reload_button_component_and_setup(config).await?
+    {
+        all_components.push(reload_component);
+        topic_handlers.add_reload(reload_topic)?;
+    }
+
+    // Handle the recent logs sensor and its publish button if enabled
+    if let Some((logs_components, logs_topic)) = create_logs_components_and_setup(config).await? {
+        all_components.extend(logs_components);
+        topic_handlers.add_publish_logs(logs_topic)?;
+    }
+
+    // With a persistent session, a probe topic lets reconnects check that
+    // the broker kept the subscriptions
+    if !config.clean_session {
+        topic_handlers.add_session_probe(session_probe_topic(config))?;
+    }
+
+    // Create the sensors published by the system monitor
+    if config.system_monitoring {
+        // Create system monitoring sensor components
+        let system_components = create_system_sensor_components(config);
+        all_components.extend(system_components);
+
+        // Create the composite system health binary sensor if configured
+        all_components.extend(create_health_component(config));
+
+        // Create the diagnostic sensor counting metrics publish failures
+        all_components.push(create_publish_failures_component(config));
+
+        // Create pressure stall sensors if enabled and supported by the kernel
+        if let Some(psi) = PsiMonitor::from_config(config) {
+            all_components.extend(psi.components(config));
+        }
+
+        // Create SMART health binary sensors for the configured devices
+        let smart_components = create_smart_components(config, &smart_devices(config));
+        all_components.extend(smart_components);
+    }
+
+    // Create the diagnostic sensor with the registered handlers, counted once
+    // all of them are set up
+    all_components.push(create_handler_counts_component(config));
+    let handler_counts = topic_handlers.counts();
+    info!(
+        "Registered {} command handlers on {} subscriptions",
+        handler_counts.handlers, handler_counts.subscriptions
+    );
 
-    // Create status sensor component
+    // Every entity except the status sensor follows the availability topic,
+    // so the status can still show "Off" or "Suspended" while offline.
+    // With D-Bus availability, an availability probe, notification availability, a
+    // per-metric topic or a status availability template, entities require all of
+    // their topics to be online. Custom availability payloads are declared on
+    // every entry, since all of these topics carry them.
+    let payloads = AvailabilityPayloads::from_config(config);
+    let probes = availability_probes(config);
+    let metric_topics = metric_availability_topics(config);
+    for (component_id, component) in all_components.iter_mut() {
+        let mut entries = vec![Availability::new(config.availability_topic.clone())];
+        if config.dbus_availability {
+            entries.push(Availability::new(config.dbus_availability_topic.clone()));
+        }
+        if let Some(probe) = probes.iter().find(|probe| &probe.entity_id == component_id) {
+            entries.push(Availability::new(probe.availability_topic.clone()));
+        }
+        if config.notify_availability && component.component_type.platform() == "notify" {
+            entries.push(Availability::new(notification_availability_topic(config)));
+        }
+        if let Some((_, topic)) = metric_topics.iter().find(|(id, _)| id == component_id) {
+            entries.push(Availability::new(topic.clone()));
+        }
+        if let Some(template) = &config.status_availability_template {
+            entries.push(
+                Availability::new(status_state_topic(&config.hostname))
+                    .with_template(template.clone()),
+            );
+        }
+
+        if !payloads.is_default() {
+            entries = entries
+                .into_iter()
+                .map(|entry| {
+                    entry.with_payloads(payloads.available.clone(), payloads.not_available.clone())
+                })
+                .collect();
+        }
+
+        *component = component.clone().with_availability(entries, "all");
+    }
+
+    // Create status sensor component; it is always present, so discovery is never empty
     let (status_id, status_component) = create_status_component(config);
     all_components.push((status_id, status_component));
 
-    // Publish unified device discovery with all components
+    // Entities are keyed by id in the discovery message, so a collision would
+    // silently drop one of them
+    check_unique_component_ids(&all_components)?;
+
+    // Retained discovery of the scheme not in use is removed: single-entity
+    // configs left by older versions or per-component discovery under every
+    // prefix, or the device discovery when publishing per component. Every
+    // built-in metric sensor is included, as older versions published them all,
+    // even when it is now disabled or system monitoring is off.
+    let mut stale_topics: Vec<String> = config
+        .discovery_prefixes
+        .iter()
+        .flat_map(|prefix| {
+            all_components.iter().map(move |(id, component)| {
+                component_discovery_topic(prefix, component.component_type.platform(), id)
+            })
+        })
+        .chain(
+            metric_component_ids(config)
+                .iter()
+                .map(|id| legacy_discovery_topic("sensor", id)),
+        )
+        .chain(config.device_discovery_topics.iter().cloned())
+        .collect();
+
     info!(
-        "Publishing unified device discovery with {} components",
+        "Publishing discovery with {} components",
         all_components.len()
     );
-    publish_unified_discovery(&client, config, all_components).await?;
+    let discovery = build_discovery_messages(config, all_components)?;
+    stale_topics.retain(|topic| !discovery.contains_topic(topic));
+    stale_topics.sort();
+    stale_topics.dedup();
 
-    info!("Discovery complete, briefly waiting...");
-    time::sleep(Duration::from_millis(500)).await;
+    // Everything published from here on runs in one background task, in order:
+    // together it queues more requests than the request channel holds, and the
+    // event loop is only polled once the main loop starts. Subscriptions come
+    // before discovery, so commands sent as soon as Home Assistant knows an
+    // entity are received. Stale configs are cleared before publishing, so Home
+    // Assistant does not drop entities it already knows from the new discovery
+    // when their old config disappears.
+    let startup_client = client.clone();
+    let mut startup_status = status_manager.clone();
+    let status_publish_attempts = config.status_publish_attempts;
+    let subscription_topics = topic_handlers.get_subscription_topics();
+    let discovery_qos = config.discovery_qos();
+    let confirmations = topic_handlers.confirmations();
+    let switch_states = initial_switch_states(config);
+    let counts_topic = handler_counts_state_topic(config);
+    let startup_handle = tokio::spawn(async move {
+        let client = startup_client;
+        if let Err(e) = startup_status.publish_state(DaemonState::Starting).await {
+            warn!("Failed to publish starting status: {}", e);
+        }
 
-    // Create status manager and publish initial status
-    debug!("Creating status manager");
-    let status_manager = StatusManager::new(config.hostname.clone(), client.clone());
-    debug!("Publishing initial 'On' status");
-    if let Err(e) = status_manager.publish_on().await {
-        warn!("Failed to publish initial status: {}", e);
-    } else {
-        debug!("Successfully published initial status");
-    }
+        if let Some((topic, birth_json)) = birth
+            && let Err(e) = publish_birth_message(&client, &topic, birth_json).await
+        {
+            error!("Failed to publish birth message: {}", e);
+        }
 
-    // Create system monitor
-    info!("Starting system monitor");
-    let mut system_monitor = SystemMonitor::new(config.sensor_topic_base.clone(), client.clone());
+        for topic in subscription_topics {
+            debug!("Subscribing to topic: {}", topic);
+            if let Err(e) = client.subscribe(&topic, QoS::AtMostOnce).await {
+                error!("Failed to subscribe to '{}': {}", topic, e);
+            }
+        }
 
-    // Start system monitoring in background
-    let monitoring_handle = tokio::spawn(async move {
-        system_monitor.run_monitoring_loop().await;
+        if let Err(e) = clear_legacy_discovery(&client, &stale_topics).await {
+            warn!("Failed to clear stale discovery topics: {}", e);
+        }
+        if let Err(e) = publish_discovery_messages(&client, &discovery, discovery_qos).await {
+            error!("Failed to publish discovery: {}", e);
+        }
+
+        info!("Discovery complete, briefly waiting...");
+        time::sleep(Duration::from_millis(500)).await;
+
+        for confirmation in &confirmations {
+            confirmation.spawn_reset(&client);
+        }
+
+        // Publish switch states once HA knows the switches, so even non-retained
+        // states arrive. Reading them may take a while, so this runs on its own.
+        if !switch_states.is_empty() {
+            let switch_client = client.clone();
+            tokio::spawn(async move {
+                publish_initial_switch_states(&switch_client, &switch_states).await;
+            });
+        }
+
+        publish_handler_counts(&client, &counts_topic, handler_counts).await;
+
+        publish_initial_status(&mut startup_status, status_publish_attempts).await;
     });
 
+    let monitoring_handle = spawn_system_monitor(config, &client, &topic_handlers);
+
     Ok((
         client,
         eventloop,
         topic_handlers,
         status_manager,
         monitoring_handle,
+        startup_handle,
     ))
 }
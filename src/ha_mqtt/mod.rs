@@ -1,12 +1,23 @@
+pub mod birth;
+pub mod client;
+pub mod device_class;
 pub mod discovery;
 pub mod handlers;
 pub mod init;
+pub mod session;
+pub mod subscriptions;
+pub mod tls;
 
 // Re-export all public items to maintain compatibility
+pub use birth::{birth_message, publish_birth_message};
+pub use client::{MqttClient, MqttEvent, MqttEventLoop};
 pub use discovery::{
-    create_shared_device, create_shared_origin, publish_discovery, publish_unified_discovery,
-    ComponentType, DeviceDiscoveryBuilder, HomeAssistantComponent, HomeAssistantDevice,
-    HomeAssistantDeviceDiscovery, HomeAssistantOrigin,
+    Availability, ComponentType, DeviceDiscoveryBuilder, DiscoveryMessages, HomeAssistantComponent,
+    HomeAssistantDevice, HomeAssistantDeviceDiscovery, HomeAssistantOrigin,
+    build_discovery_messages, check_unique_component_ids, clear_legacy_discovery,
+    component_discovery_topic, create_shared_device, create_shared_origin, legacy_discovery_topic,
+    publish_discovery, publish_discovery_messages,
 };
-pub use handlers::{TopicHandler, TopicHandlers};
-pub use init::initialize_mqtt_connection;
+pub use handlers::{HandlerCounts, TopicHandler, TopicHandlers};
+pub use init::{initialize_mqtt_connection, spawn_system_monitor};
+pub use subscriptions::SubscriptionTracker;
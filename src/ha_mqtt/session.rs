@@ -0,0 +1,53 @@
+// Session verification - with a persistent session the broker keeps our
+// subscriptions across reconnects, which a probe round-trip confirms before
+// falling back to subscribing again
+
+use rumqttc::QoS;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error};
+
+use super::MqttClient;
+use crate::utils::Config;
+
+/// How long the probe message has to come back before the subscriptions
+/// are considered lost
+pub const SESSION_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Topic only this daemon subscribes and publishes to, used to check that
+/// the subscriptions of a resumed session are still in place
+pub fn session_probe_topic(config: &Config) -> String {
+    format!("homeassistant/device/{}/session_probe", config.hostname)
+}
+
+/// Publish a probe message in the background; the main loop keeps polling
+/// the event loop meanwhile, which a full request channel needs
+pub fn spawn_session_probe(client: &MqttClient, topic: String) {
+    let client = client.clone();
+    tokio::spawn(async move {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        debug!("Publishing session probe to '{}'", topic);
+        if let Err(e) = client
+            .publish(&topic, QoS::AtLeastOnce, false, timestamp.to_string())
+            .await
+        {
+            error!("Failed to publish session probe: {}", e);
+        }
+    });
+}
+
+/// Subscribe to `topics` again in the background, after the broker dropped
+/// the session or the probe did not come back
+pub fn spawn_resubscribe(client: &MqttClient, topics: Vec<String>) {
+    let client = client.clone();
+    tokio::spawn(async move {
+        for topic in topics {
+            debug!("Re-subscribing to topic: {}", topic);
+            if let Err(e) = client.subscribe(&topic, QoS::AtMostOnce).await {
+                error!("Failed to re-subscribe to '{}': {}", topic, e);
+            }
+        }
+    });
+}
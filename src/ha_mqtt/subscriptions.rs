@@ -0,0 +1,97 @@
+// Subscription tracking - matches subscribe requests to the broker's SubAcks,
+// since `subscribe` returns as soon as the request is queued
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use super::MqttEvent;
+
+/// How long the broker has to acknowledge a subscription
+const SUBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct PendingSubscriptions {
+    /// Requested topics not yet sent, in request order, which is also the
+    /// order the event loop sends them and assigns packet ids
+    queued: VecDeque<(String, Instant)>,
+    /// Sent subscriptions awaiting a SubAck, by packet id
+    sent: HashMap<u16, (String, Instant)>,
+}
+
+/// Subscriptions awaiting the broker's acknowledgement, shared by all clones
+/// of a client
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionTracker {
+    pending: Arc<Mutex<PendingSubscriptions>>,
+}
+
+impl SubscriptionTracker {
+    fn pending(&self) -> std::sync::MutexGuard<'_, PendingSubscriptions> {
+        // The data stays consistent even if a holder panicked
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Expect a SubAck for `topic`, whose subscribe request was just queued
+    pub fn record_request(&self, topic: String) {
+        self.pending().queued.push_back((topic, Instant::now()));
+    }
+
+    /// Match sent subscribe packets and SubAcks against the pending subscriptions,
+    /// logging an error for every topic the broker rejects
+    pub fn record_event(&self, event: &MqttEvent) {
+        if let Some(pkid) = event.outgoing_subscribe() {
+            let mut pending = self.pending();
+            if let Some(subscription) = pending.queued.pop_front() {
+                pending.sent.insert(pkid, subscription);
+            }
+        } else if let Some((pkid, accepted)) = event.sub_ack() {
+            let Some((topic, _)) = self.pending().sent.remove(&pkid) else {
+                return;
+            };
+            if accepted {
+                debug!("Subscription to '{}' acknowledged", topic);
+            } else {
+                error!(
+                    "Broker rejected the subscription to '{}'; its entity will not respond",
+                    topic
+                );
+            }
+        }
+    }
+
+    /// Log an error for, and stop tracking, every subscription that has not
+    /// been acknowledged within the timeout
+    pub fn check_overdue(&self) {
+        let mut pending = self.pending();
+        let overdue = |requested: &Instant| requested.elapsed() > SUBACK_TIMEOUT;
+
+        let mut missing: Vec<String> = pending
+            .sent
+            .values()
+            .filter(|(_, requested)| overdue(requested))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        pending.sent.retain(|_, (_, requested)| !overdue(requested));
+
+        // Requests are queued in order, so the overdue ones are at the front
+        while let Some((_, requested)) = pending.queued.front()
+            && overdue(requested)
+        {
+            if let Some((topic, _)) = pending.queued.pop_front() {
+                missing.push(topic);
+            }
+        }
+
+        for topic in missing {
+            error!(
+                "Subscription to '{}' was not acknowledged within {}s; its entity may not respond",
+                topic,
+                SUBACK_TIMEOUT.as_secs()
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// TLS transport - rustls client configuration for `tls`, `tls_insecure` and `tls_server_name`
+
+use rumqttc::tokio_rustls::rustls::client::WebPkiServerVerifier;
+use rumqttc::tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use rumqttc::tokio_rustls::rustls::crypto::{
+    WebPkiSupportedAlgorithms, ring, verify_tls12_signature, verify_tls13_signature,
+};
+use rumqttc::tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rumqttc::tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, SignatureScheme,
+};
+use rumqttc::{TlsConfiguration, Transport};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::utils::Config;
+
+/// Build the TLS transport for the broker connection, or `None` for plain TCP
+pub fn transport(config: &Config) -> Result<Option<Transport>> {
+    if !config.tls {
+        return Ok(None);
+    }
+
+    let verifier: Arc<dyn ServerCertVerifier> = if config.tls_insecure {
+        warn!(
+            "tls_insecure is set: the broker certificate is NOT verified and the connection is open to interception"
+        );
+        Arc::new(NoVerification {
+            algorithms: ring::default_provider().signature_verification_algorithms,
+        })
+    } else {
+        let roots = Arc::new(native_roots()?);
+        let webpki = WebPkiServerVerifier::builder(roots)
+            .build()
+            .map_err(|e| Error::Config(format!("Failed to create TLS verifier: {}", e)))?;
+        match &config.tls_server_name {
+            Some(name) => {
+                let server_name = ServerName::try_from(name.clone()).map_err(|e| {
+                    Error::Config(format!("Invalid tls_server_name '{}': {}", name, e))
+                })?;
+                debug!("Verifying broker certificate against '{}'", name);
+                Arc::new(ServerNameOverride {
+                    inner: webpki,
+                    server_name,
+                })
+            }
+            None => webpki,
+        }
+    };
+
+    let tls_config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    Ok(Some(Transport::tls_with_config(TlsConfiguration::Rustls(
+        Arc::new(tls_config),
+    ))))
+}
+
+/// Trust anchors from the operating system's certificate store
+fn native_roots() -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) =
+        roots.add_parsable_certificates(rustls_native_certs::load_native_certs()?);
+    debug!(
+        "Loaded {} native root certificates ({} ignored)",
+        added, ignored
+    );
+    if roots.is_empty() {
+        return Err(Error::Config(
+            "No usable root certificates found in the system certificate store".to_string(),
+        ));
+    }
+    Ok(roots)
+}
+
+/// Verifies the broker certificate against a configured name instead of the
+/// connection address, e.g. when connecting by IP
+#[derive(Debug)]
+struct ServerNameOverride {
+    inner: Arc<WebPkiServerVerifier>,
+    server_name: ServerName<'static>,
+}
+
+impl ServerCertVerifier for ServerNameOverride {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, TlsError> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            &self.server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Accepts any broker certificate (`tls_insecure`). Handshake signatures are
+/// still checked, so the connection is encrypted but not authenticated.
+#[derive(Debug)]
+struct NoVerification {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, TlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
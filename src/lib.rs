@@ -0,0 +1,11 @@
+// hars-imp library - exposes the daemon's building blocks for reuse, e.g.
+// collecting system metrics with `components::SystemCollector` without MQTT
+
+pub mod components;
+pub mod crash;
+pub mod dbus;
+pub mod error;
+pub mod ha_mqtt;
+pub mod reload;
+pub mod shutdown;
+pub mod utils;
@@ -1,36 +1,95 @@
-use rumqttc::{Event, Packet};
+use std::io::Write;
 use std::time::Duration;
 use tokio::time;
 use tracing::{debug, error, info, trace, warn};
 
-pub mod components;
-pub mod dbus;
-pub mod ha_mqtt;
-pub mod shutdown;
-pub mod utils;
+use hars_imp::components::{
+    FsTypeFilter, SystemCollector, SystemMonitor, list_disks, logs_state_topic, publish_recent_logs,
+};
+use hars_imp::crash::{install_panic_hook, set_crash_reporter};
+use hars_imp::dbus::{self, DaemonState, handle_power_events, setup_power_monitoring};
+use hars_imp::error::{Error, Result};
+use hars_imp::ha_mqtt::session::{
+    SESSION_PROBE_TIMEOUT, session_probe_topic, spawn_resubscribe, spawn_session_probe,
+};
+use hars_imp::ha_mqtt::{initialize_mqtt_connection, spawn_system_monitor};
+use hars_imp::reload::ReloadTarget;
+use hars_imp::shutdown::{ShutdownHandler, perform_graceful_shutdown, restart_daemon};
+use hars_imp::utils::config::CONFIG_TEMPLATE;
+use hars_imp::utils::logging::log_publish_requested;
+use hars_imp::utils::{CliArgs, Config, init_tracing};
 
-use dbus::{handle_power_events, setup_power_monitoring};
-use ha_mqtt::initialize_mqtt_connection;
-use shutdown::{perform_graceful_shutdown, ShutdownHandler};
-use utils::{init_tracing, Config};
+/// How often the main loop checks that the system monitor task is still running
+const MONITOR_WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A system monitor without a completed update for this long is considered
+/// wedged, e.g. in a hanging sysinfo call; five regular metrics intervals
+const MONITOR_STALL_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Load configuration
-    let config = Config::load()?;
+async fn main() -> Result<()> {
+    let cli = CliArgs::parse()?;
+
+    if cli.print_config_path {
+        println!("{}", Config::get_config_path()?);
+        return Ok(());
+    }
+
+    if let Some(path) = &cli.generate_config {
+        return generate_config(path.as_deref());
+    }
+
+    if cli.list_disks {
+        print_disks();
+        return Ok(());
+    }
+
+    if cli.metrics {
+        return print_metrics().await;
+    }
+
+    if let Some((summary, message)) = &cli.test_notification {
+        return send_test_notification(summary, message).await;
+    }
+
+    // Load configuration; replaced when it is reloaded over MQTT
+    let mut config = Config::load()?;
 
-    // Initialize tracing with the configured log level
-    init_tracing(&config.log_level)?;
+    // Initialize tracing with the log level from the command line, RUST_LOG
+    // or the config, in that order
+    let log_level = cli
+        .log_level
+        .clone()
+        .or_else(|| {
+            std::env::var("RUST_LOG")
+                .ok()
+                .filter(|level| !level.is_empty())
+        })
+        .unwrap_or_else(|| config.log_level.clone());
+    init_tracing(
+        &log_level,
+        config.log_buffer_lines,
+        config.publish_logs_on_error,
+    )?;
+
+    if cli.once {
+        return run_once(&config).await;
+    }
 
     info!("Starting MQTT daemon for hostname: {}", config.hostname);
+
+    // Publish a crash status if any thread panics
+    set_crash_reporter(&config);
+    install_panic_hook();
+
     info!(
         "Connecting to MQTT broker: {}:{}",
-        config.mqtt_url, config.mqtt_port
+        config.mqtt_url, config.broker_port
     );
-    debug!("Log level set to: {}", config.log_level);
+    debug!("Log level set to: {}", log_level);
 
     // Set up power monitoring
-    let (mut power_manager, _power_monitor_handle) = setup_power_monitoring().await;
+    let (mut power_manager, _power_monitor_handle) = setup_power_monitoring(&config).await;
 
     // Initialize MQTT connection
     let (
@@ -39,11 +98,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         mut topic_handlers,
         mut status_manager,
         mut system_monitor_handle,
+        _startup_handle,
     ) = initialize_mqtt_connection(&config).await?;
 
+    // In the background, as the startup task may have filled the request
+    // channel before the main loop polls the event loop
+    let dbus_status = status_manager.clone();
+    let dbus_connected = power_manager.is_dbus_connected();
+    tokio::spawn(async move {
+        if let Err(e) = dbus_status.publish_dbus_availability(dbus_connected).await {
+            warn!("Failed to publish D-Bus availability: {}", e);
+        }
+    });
+
     // Setup shutdown signal handlers
     let mut shutdown_handler = ShutdownHandler::new()?;
 
+    // The system monitor is aborted on suspend and restarted on resume;
+    // the watchdog leaves it alone in between
+    let mut monitor_suspended = false;
+    let mut monitor_watchdog = time::interval(MONITOR_WATCHDOG_INTERVAL);
+
+    // Last successful event loop poll, for the activity watchdog
+    let activity_timeout = config.activity_timeout_secs.map(Duration::from_secs);
+    let mut last_activity = time::Instant::now();
+
+    // Reported as the Degraded state while metrics publishing keeps failing
+    let mut metrics_degraded = topic_handlers.metrics_degraded.subscribe();
+
+    // Deadline of a session probe awaiting its echo after a reconnect
+    let mut session_probe_deadline: Option<time::Instant> = None;
+
     // Main event loop
     info!("Starting main event loop");
     loop {
@@ -51,14 +136,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             res = eventloop.poll() => {
                 match res {
                     Ok(notification) => {
-                        match notification {
-                            Event::Incoming(Packet::Publish(publish)) => {
-                                let topic = &publish.topic;
-                                let payload = String::from_utf8_lossy(&publish.payload);
+                        last_activity = time::Instant::now();
+                        client.subscriptions().record_event(&notification);
+                        client.subscriptions().check_overdue();
+                        if notification.is_conn_ack() && status_manager.record_conn_ack() {
+                            warn!(
+                                "Reconnected to MQTT broker ({} reconnections since startup)",
+                                status_manager.reconnect_count()
+                            );
+                            let state = if *metrics_degraded.borrow() {
+                                DaemonState::Degraded
+                            } else {
+                                DaemonState::Connected
+                            };
+                            if let Err(e) = status_manager.publish_state(state).await {
+                                error!("Failed to publish status after reconnection: {}", e);
+                            }
+                            if config.notify_on_reconnect {
+                                notify_reconnected(&config, status_manager.reconnect_count());
+                            }
+                            if !config.clean_session && notification.session_present() {
+                                // The broker claims to have kept the subscriptions; check
+                                // before trusting it
+                                spawn_session_probe(&client, session_probe_topic(&config));
+                                session_probe_deadline =
+                                    Some(time::Instant::now() + SESSION_PROBE_TIMEOUT);
+                            } else {
+                                info!("Broker started a new session, re-subscribing");
+                                spawn_resubscribe(&client, topic_handlers.get_subscription_topics());
+                            }
+                        }
+
+                        match notification.as_publish() {
+                            Some((topic, payload)) => {
+                                let topic = topic.as_ref();
+                                let payload = String::from_utf8_lossy(payload);
                                 trace!("Received message on topic '{}': {}", topic, payload);
 
                                 // Check if this message should be handled by our topic handlers
-                                match topic_handlers.handle_message(topic, &payload, &client).await {
+                                let retained = notification.is_retained_publish();
+                                match topic_handlers.handle_message(topic, &payload, retained, &client).await {
                                     Ok(true) => {
                                         // Message was handled by a topic handler
                                     }
@@ -71,9 +188,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     }
                                 }
                             }
-                            event => {
+                            None => {
                                 // Other events (connections, pings, etc.)
-                                debug!("MQTT event: {:?}", event);
+                                debug!("MQTT event: {:?}", notification);
                             }
                         }
                     }
@@ -87,6 +204,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             power_event = handle_power_events(&mut power_manager) => {
                 if let Some(event) = power_event {
+                    monitor_suspended = matches!(event, dbus::PowerEvent::Suspending);
                     let mut handler = dbus::PowerEventHandler::new(
                         &mut power_manager,
                         &mut client,
@@ -96,12 +214,153 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         &mut system_monitor_handle,
                         &config,
                     );
-                    handler.handle_event(event).await;
+
+                    // Resume handling can block on a slow reconnect, so keep
+                    // listening for shutdown signals while it runs
+                    let interrupted_by = tokio::select! {
+                        _ = handler.handle_event(event) => None,
+                        signal = shutdown_handler.wait_for_shutdown_signal() => Some(signal),
+                    };
+                    if let Some(signal) = interrupted_by {
+                        info!("{}", signal.description());
+                        warn!("Abandoning power event handling to shut down");
+                        perform_graceful_shutdown(&mut status_manager, &mut client, &mut eventloop, Some(&mut power_manager)).await?;
+                        break;
+                    }
+                    // Time spent suspended or reconnecting does not count as inactivity
+                    last_activity = time::Instant::now();
+                    // The topic handlers are replaced on a full reconnect
+                    metrics_degraded = topic_handlers.metrics_degraded.subscribe();
+                    // Resuming subscribes again anyway
+                    session_probe_deadline = None;
                 } else {
                     // Power event channel closed, power monitoring stopped
                     debug!("Power monitoring stopped");
                 }
             }
+            _ = monitor_watchdog.tick() => {
+                let last_update = *topic_handlers.monitor_heartbeat.borrow();
+                if !monitor_suspended
+                    && let Some(handle) = system_monitor_handle.as_mut()
+                    && !handle.is_finished()
+                    && last_update.elapsed() > MONITOR_STALL_TIMEOUT
+                {
+                    // A blocking call cannot be interrupted, but aborting frees
+                    // the task as soon as it yields and a fresh monitor takes over
+                    error!(
+                        "System monitor has not completed an update for {}s, restarting it",
+                        last_update.elapsed().as_secs()
+                    );
+                    handle.abort();
+                    system_monitor_handle =
+                        spawn_system_monitor(&config, &client, &topic_handlers);
+                } else if !monitor_suspended
+                    && let Some(handle) = system_monitor_handle.as_mut()
+                    && handle.is_finished()
+                {
+                    match handle.await {
+                        Err(e) if e.is_panic() => error!("System monitor panicked: {}", e),
+                        Err(e) => warn!("System monitor was stopped unexpectedly: {}", e),
+                        Ok(()) => warn!("System monitor exited unexpectedly"),
+                    }
+                    info!("Restarting system monitor");
+                    system_monitor_handle =
+                        spawn_system_monitor(&config, &client, &topic_handlers);
+                }
+            }
+            _ = time::sleep_until(last_activity + activity_timeout.unwrap_or_default()), if activity_timeout.is_some() && !monitor_suspended => {
+                // The broker link is presumably dead, so skip the graceful shutdown
+                // and leave the last will to mark the device offline
+                error!(
+                    "No MQTT activity for {}s, exiting so the service manager can restart the daemon",
+                    last_activity.elapsed().as_secs()
+                );
+                return Err(Error::Timeout("no MQTT activity".to_string()));
+            }
+            _ = topic_handlers.session_probe_echo.notified() => {
+                if session_probe_deadline.take().is_some() {
+                    info!("Persistent session kept the subscriptions");
+                }
+            }
+            _ = time::sleep_until(session_probe_deadline.unwrap_or_else(time::Instant::now)), if session_probe_deadline.is_some() => {
+                session_probe_deadline = None;
+                warn!(
+                    "Session probe not received within {}s, re-subscribing",
+                    SESSION_PROBE_TIMEOUT.as_secs()
+                );
+                spawn_resubscribe(&client, topic_handlers.get_subscription_topics());
+            }
+            Ok(()) = metrics_degraded.changed() => {
+                let degraded = *metrics_degraded.borrow_and_update();
+                // Only a running daemon switches between the two, not a suspended one
+                if status_manager.state().is_some_and(DaemonState::is_running) {
+                    let state = if degraded {
+                        warn!("Metrics publishing keeps failing, reporting degraded status");
+                        DaemonState::Degraded
+                    } else {
+                        info!("Metrics publishing recovered");
+                        DaemonState::Connected
+                    };
+                    if let Err(e) = status_manager.publish_state(state).await {
+                        error!("Failed to publish status: {}", e);
+                    }
+                }
+            }
+            _ = log_publish_requested() => {
+                // Publishing can wait on a full request channel, so keep it off the main loop
+                let logs_client = client.clone();
+                let logs_topic = logs_state_topic(&config);
+                tokio::spawn(async move {
+                    publish_recent_logs(&logs_client, &logs_topic).await;
+                });
+            }
+            _ = topic_handlers.reload_trigger.notified() => {
+                info!("Reloading configuration on request from Home Assistant");
+                let mut target = ReloadTarget {
+                    client: &mut client,
+                    eventloop: &mut eventloop,
+                    topic_handlers: &mut topic_handlers,
+                    status_manager: &mut status_manager,
+                    system_monitor_handle: &mut system_monitor_handle,
+                };
+
+                // Setting up the connection again can block on a slow broker,
+                // so keep listening for shutdown signals meanwhile
+                let outcome = tokio::select! {
+                    result = target.reload(&config) => Ok(result),
+                    signal = shutdown_handler.wait_for_shutdown_signal() => Err(signal),
+                };
+                match outcome {
+                    Ok(Ok(new_config)) => {
+                        config = new_config;
+                        set_crash_reporter(&config);
+                        if let Err(e) = status_manager
+                            .publish_dbus_availability(power_manager.is_dbus_connected())
+                            .await
+                        {
+                            warn!("Failed to publish D-Bus availability: {}", e);
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        error!("Config reload failed: {}", e);
+                    }
+                    Err(signal) => {
+                        info!("{}", signal.description());
+                        warn!("Abandoning config reload to shut down");
+                        perform_graceful_shutdown(&mut status_manager, &mut client, &mut eventloop, Some(&mut power_manager)).await?;
+                        break;
+                    }
+                }
+                last_activity = time::Instant::now();
+                // The topic handlers are replaced by a reload
+                metrics_degraded = topic_handlers.metrics_degraded.subscribe();
+                session_probe_deadline = None;
+            }
+            _ = topic_handlers.restart_trigger.notified() => {
+                warn!("Restarting daemon on request from Home Assistant");
+                perform_graceful_shutdown(&mut status_manager, &mut client, &mut eventloop, Some(&mut power_manager)).await?;
+                return Err(restart_daemon());
+            }
             signal = shutdown_handler.wait_for_shutdown_signal() => {
                 info!("{}", signal.description());
                 perform_graceful_shutdown(&mut status_manager, &mut client, &mut eventloop, Some(&mut power_manager)).await?;
@@ -113,3 +372,170 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("MQTT daemon shut down.");
     Ok(())
 }
+
+/// Send a desktop notification about a broker reconnection in the background,
+/// so a slow notification daemon does not hold up the event loop
+fn notify_reconnected(config: &Config, reconnect_count: u32) {
+    let options = dbus::NotificationOptions::from_config(config);
+    let message = format!(
+        "Reconnected to the MQTT broker {} ({} reconnections since startup)",
+        config.mqtt_url, reconnect_count
+    );
+    tokio::spawn(async move {
+        if let Err(e) =
+            dbus::send_desktop_notification_with("MQTT reconnected", &message, 1, &options).await
+        {
+            warn!("Failed to send reconnection notification: {}", e);
+        }
+    });
+}
+
+/// Print every disk with its space, marking the one used for the disk sensors
+fn print_disks() {
+    const BYTES_TO_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+    // Apply the configured filesystem filter if there is a readable config
+    let filter = Config::load()
+        .map(|config| FsTypeFilter::from_config(&config))
+        .unwrap_or_default();
+
+    println!(
+        "{:<3} {:<24} {:<32} {:<12} {:>12} {:>12}",
+        "", "DEVICE", "MOUNT POINT", "TYPE", "TOTAL GB", "FREE GB"
+    );
+    for disk in list_disks(&filter) {
+        println!(
+            "{:<3} {:<24} {:<32} {:<12} {:>12.2} {:>12.2}",
+            if disk.selected { "*" } else { "" },
+            disk.name,
+            disk.mount_point,
+            disk.file_system,
+            disk.total_bytes as f64 / BYTES_TO_GB,
+            disk.available_bytes as f64 / BYTES_TO_GB
+        );
+    }
+    println!("\n* = disk reported by the disk sensors");
+}
+
+/// Collect one round of system metrics and print it as JSON, without MQTT or D-Bus
+async fn print_metrics() -> Result<()> {
+    // Apply the configured collection settings if there is a readable config
+    let config = Config::load().ok();
+    let mut collector = config
+        .as_ref()
+        .map(SystemCollector::from_config)
+        .unwrap_or_default();
+    let unit = config
+        .as_ref()
+        .map(|config| config.metrics_unit)
+        .unwrap_or_default();
+
+    collector.warm_up_cpu().await;
+    let metrics = collector.collect().to_json(unit)?;
+    println!("{}", serde_json::to_string_pretty(&metrics)?);
+    Ok(())
+}
+
+/// Write the commented config template to `path`, or stdout without one.
+/// An existing file is never overwritten.
+fn generate_config(path: Option<&str>) -> Result<()> {
+    let Some(path) = path else {
+        print!("{}", CONFIG_TEMPLATE);
+        return Ok(());
+    };
+
+    let mut file = match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(Error::Config(format!(
+                "{} already exists, not overwriting it",
+                path
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    file.write_all(CONFIG_TEMPLATE.as_bytes())?;
+    println!("Wrote config template to {}", path);
+    Ok(())
+}
+
+/// Send a single desktop notification to check the D-Bus notification path
+async fn send_test_notification(summary: &str, message: &str) -> Result<()> {
+    init_tracing("info", None, false)?;
+
+    // Use the configured notification bus if there is a readable config
+    let options = Config::load()
+        .map(|config| dbus::NotificationOptions::from_config(&config))
+        .unwrap_or_default();
+
+    match dbus::send_desktop_notification_with(summary, message, 1, &options).await {
+        Ok(()) => {
+            println!("Test notification sent");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Failed to send test notification: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Publish discovery, status and a single round of metrics, then disconnect
+async fn run_once(config: &Config) -> Result<()> {
+    info!(
+        "Running in single-shot mode for hostname: {}",
+        config.hostname
+    );
+
+    let (
+        client,
+        mut eventloop,
+        _topic_handlers,
+        mut status_manager,
+        system_monitor_handle,
+        startup_handle,
+    ) = initialize_mqtt_connection(config).await?;
+
+    // Metrics are published once below instead of by the background loop
+    if let Some(handle) = system_monitor_handle {
+        handle.abort();
+    }
+    let mut system_monitor = config
+        .system_monitoring
+        .then(|| SystemMonitor::from_config(config, client.clone()));
+
+    let publish = async {
+        // Discovery and the startup statuses go out first
+        if let Err(e) = startup_handle.await {
+            warn!("Startup publishing did not complete: {}", e);
+        }
+        if let Some(system_monitor) = system_monitor.as_mut() {
+            system_monitor.publish_once().await?;
+        }
+        // A clean disconnect does not trigger the last will, so mark the
+        // device offline explicitly
+        status_manager.publish_state(DaemonState::Off).await?;
+        client.disconnect().await?;
+        Ok::<(), Error>(())
+    };
+
+    // Drive the event loop until the queued messages and the disconnect are sent
+    let drive = async {
+        loop {
+            let event = eventloop.poll().await?;
+            if event.is_outgoing_disconnect() {
+                return Ok::<(), Error>(());
+            }
+            trace!("MQTT event: {:?}", event);
+        }
+    };
+
+    tokio::try_join!(publish, drive)?;
+
+    info!("Single-shot publish complete");
+    Ok(())
+}
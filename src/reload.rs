@@ -0,0 +1,102 @@
+// Config reload - re-reads the configuration and sets up the MQTT connection,
+// entities and discovery again without restarting the process
+
+use crate::dbus::StatusManager;
+use crate::error::Result;
+use crate::ha_mqtt::{MqttClient, MqttEventLoop, TopicHandlers, initialize_mqtt_connection};
+use crate::shutdown::{ShutdownScenario, perform_graceful_mqtt_shutdown};
+use crate::utils::Config;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Warn about changed settings that are only applied at startup
+fn warn_about_restart_only_changes(current: &Config, new: &Config) {
+    let changed = [
+        ("log_level", current.log_level != new.log_level),
+        (
+            "log_buffer_lines",
+            current.log_buffer_lines != new.log_buffer_lines,
+        ),
+        (
+            "publish_logs_on_error",
+            current.publish_logs_on_error != new.publish_logs_on_error,
+        ),
+        (
+            "dbus_timeout_ms",
+            current.dbus_timeout_ms != new.dbus_timeout_ms,
+        ),
+    ];
+    for (name, _) in changed.iter().filter(|(_, changed)| *changed) {
+        warn!("Changed {} only takes effect after a restart", name);
+    }
+}
+
+/// The parts of the running daemon replaced by a reload
+pub struct ReloadTarget<'a> {
+    pub client: &'a mut MqttClient,
+    pub eventloop: &'a mut MqttEventLoop,
+    pub topic_handlers: &'a mut TopicHandlers,
+    pub status_manager: &'a mut StatusManager,
+    pub system_monitor_handle: &'a mut Option<JoinHandle<()>>,
+}
+
+impl ReloadTarget<'_> {
+    /// Load the config file again and, if it is valid, re-initialize the MQTT
+    /// connection with it. Returns the new config.
+    ///
+    /// An unreadable or invalid config is returned as an error before anything
+    /// is torn down, so the daemon keeps running with `current`. If setting up
+    /// the connection with the new config fails, the current config is set up
+    /// again instead.
+    pub async fn reload(&mut self, current: &Config) -> Result<Config> {
+        let new_config = Config::load()?;
+        warn_about_restart_only_changes(current, &new_config);
+
+        if let Some(handle) = self.system_monitor_handle.take() {
+            handle.abort();
+        }
+        if let Err(e) = perform_graceful_mqtt_shutdown(
+            self.status_manager,
+            self.client,
+            self.eventloop,
+            ShutdownScenario::Reload,
+        )
+        .await
+        {
+            warn!("Failed to shut down MQTT before reloading: {}", e);
+        }
+
+        match self.reinitialize(&new_config).await {
+            Ok(()) => {
+                info!("Configuration reloaded");
+                Ok(new_config)
+            }
+            Err(e) => {
+                error!(
+                    "Failed to set up the reloaded configuration: {}, restoring the previous one",
+                    e
+                );
+                if let Err(restore_error) = self.reinitialize(current).await {
+                    error!(
+                        "Failed to restore the previous configuration: {}",
+                        restore_error
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn reinitialize(&mut self, config: &Config) -> Result<()> {
+        let (client, eventloop, topic_handlers, mut status_manager, system_monitor_handle, _) =
+            initialize_mqtt_connection(config).await?;
+        status_manager.restore_reconnect_count(self.status_manager.reconnect_count());
+
+        *self.client = client;
+        *self.eventloop = eventloop;
+        *self.topic_handlers = topic_handlers;
+        *self.status_manager = status_manager;
+        *self.system_monitor_handle = system_monitor_handle;
+        Ok(())
+    }
+}
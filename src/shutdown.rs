@@ -1,9 +1,16 @@
-use crate::dbus::{PowerManager, StatusManager};
-use rumqttc::{AsyncClient, EventLoop};
+use crate::dbus::{DaemonState, PowerManager, StatusManager};
+use crate::error::{Error, Result};
+use crate::ha_mqtt::{MqttClient, MqttEventLoop};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
 use std::time::Duration;
-use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::signal::unix::{Signal, SignalKind, signal};
 use tokio::time;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Upper bound for draining MQTT on shutdown while the shutdown inhibitor is
+/// held, below logind's default `InhibitDelayMaxSec` of 5 seconds
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(4);
 
 pub struct ShutdownHandler {
     sigterm: Signal,
@@ -11,7 +18,7 @@ pub struct ShutdownHandler {
 }
 
 impl ShutdownHandler {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new() -> Result<Self> {
         let sigterm = signal(SignalKind::terminate())?;
         let sigint = signal(SignalKind::interrupt())?;
 
@@ -48,6 +55,8 @@ pub enum ShutdownScenario {
     FullShutdown,
     /// System suspend - application will resume later
     Suspend,
+    /// Config reload - the connection is set up again right away
+    Reload,
 }
 
 impl ShutdownScenario {
@@ -55,115 +64,134 @@ impl ShutdownScenario {
         match self {
             ShutdownScenario::FullShutdown => "full shutdown",
             ShutdownScenario::Suspend => "suspend",
+            ShutdownScenario::Reload => "config reload",
         }
     }
 }
 
 /// Gracefully shut down MQTT connection with proper event queue draining
-/// This function can be used for both full shutdown and suspend scenarios
+/// This function can be used for both full shutdown and suspend scenarios.
+/// Returns whether the disconnect was sent before the drain timeout.
 pub async fn perform_graceful_mqtt_shutdown(
     status_manager: &mut StatusManager,
-    client: &mut AsyncClient,
-    eventloop: &mut EventLoop,
+    client: &mut MqttClient,
+    eventloop: &mut MqttEventLoop,
     scenario: ShutdownScenario,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<bool> {
     info!(
         "Performing graceful MQTT shutdown for {}...",
         scenario.description()
     );
 
-    // Publish appropriate status message based on scenario
-    let status_result = match scenario {
-        ShutdownScenario::FullShutdown => status_manager.publish_off().await,
-        ShutdownScenario::Suspend => status_manager.publish_suspended().await,
+    // States published for the scenario, in order
+    let states: &[DaemonState] = match scenario {
+        ShutdownScenario::FullShutdown => &[DaemonState::ShuttingDown, DaemonState::Off],
+        ShutdownScenario::Suspend => &[DaemonState::Suspended],
+        ShutdownScenario::Reload => &[DaemonState::Starting],
     };
 
-    if let Err(e) = status_result {
-        error!("Failed to publish {} status: {}", scenario.description(), e);
-    } else {
-        info!(
-            "{} status message queued successfully",
-            scenario.description()
-        );
-    }
-
-    // Process any pending events to ensure message is sent
-    info!("Processing final MQTT events to drain queue...");
-    let max_attempts = 2;
-    for i in 0..max_attempts {
-        debug!(
-            "Processing {} events (attempt {}/{})",
-            scenario.description(),
-            i + 1,
-            max_attempts
-        );
-        match eventloop.poll().await {
-            Ok(event) => {
-                debug!("Processing {} event: {:?}", scenario.description(), event);
-            }
-            Err(e) => {
-                debug!(
-                    "Event processing error during {}: {}",
-                    scenario.description(),
-                    e
-                );
-                break;
+    // Queue the final states and the disconnect
+    let publish = async {
+        for state in states {
+            if let Err(e) = status_manager.publish_state(*state).await {
+                error!("Failed to publish {} status: {}", scenario.description(), e);
             }
         }
-        time::sleep(Duration::from_millis(5)).await;
-    }
+        info!("Disconnecting from MQTT broker...");
+        if let Err(e) = client.disconnect().await {
+            error!("Error disconnecting from MQTT broker: {}", e);
+        }
+    };
 
-    // Explicitly disconnect the MQTT client
-    info!("Disconnecting from MQTT broker...");
-    match client.disconnect().await {
-        Ok(_) => debug!("MQTT client disconnected cleanly"),
-        Err(e) => {
-            // Handle expected disconnect errors more gracefully
-            if e.to_string().contains("connection closed by peer")
-                || e.to_string().contains("ConnectionAborted")
-            {
-                debug!(
-                    "Expected disconnect behavior during {}: {}",
-                    scenario.description(),
-                    e
-                );
-            } else {
-                error!("Error disconnecting from MQTT broker: {}", e);
+    // The event loop sends one request per poll, so keep polling until the
+    // queued disconnect has gone out
+    let drain = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(event) if event.is_outgoing_disconnect() => {
+                    debug!("MQTT client disconnected cleanly");
+                    return;
+                }
+                Ok(event) => {
+                    debug!("Processing {} event: {:?}", scenario.description(), event);
+                }
+                Err(e) => {
+                    // Nothing more gets sent once the connection is gone
+                    debug!(
+                        "Event processing error during {}: {}",
+                        scenario.description(),
+                        e
+                    );
+                    return;
+                }
             }
         }
+    };
+
+    info!("Processing final MQTT events to drain queue...");
+    let drained = time::timeout(SHUTDOWN_DRAIN_TIMEOUT, async {
+        tokio::join!(publish, drain)
+    })
+    .await
+    .is_ok();
+    if !drained {
+        warn!(
+            "MQTT queue for {} not drained within {}s, giving up on it",
+            scenario.description(),
+            SHUTDOWN_DRAIN_TIMEOUT.as_secs()
+        );
     }
 
     info!(
         "Graceful MQTT shutdown for {} completed",
         scenario.description()
     );
-    Ok(())
+    Ok(drained)
 }
 
 /// Perform complete graceful shutdown for full application termination
 pub async fn perform_graceful_shutdown(
     status_manager: &mut StatusManager,
-    client: &mut AsyncClient,
-    eventloop: &mut EventLoop,
+    client: &mut MqttClient,
+    eventloop: &mut MqttEventLoop,
     power_manager: Option<&mut PowerManager>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<()> {
     info!("Performing graceful shutdown...");
 
-    // Release shutdown inhibitor first to signal we're handling the shutdown
-    if let Some(pm) = power_manager {
-        pm.release_shutdown_inhibitor();
-        debug!("Released shutdown inhibitor to acknowledge shutdown signal");
-    }
-
-    // Use the general MQTT shutdown function
-    perform_graceful_mqtt_shutdown(
+    // Hold the shutdown inhibitor until the disconnect has gone out, so a
+    // system shutdown cannot cut off the final status; the drain is bounded
+    // by its timeout, below logind's inhibitor delay
+    let result = perform_graceful_mqtt_shutdown(
         status_manager,
         client,
         eventloop,
         ShutdownScenario::FullShutdown,
     )
-    .await?;
+    .await;
+
+    if let Some(pm) = power_manager {
+        pm.release_shutdown_inhibitor();
+        match result {
+            Ok(true) => debug!("Released shutdown inhibitor after draining MQTT"),
+            _ => debug!("Released shutdown inhibitor without a complete MQTT drain"),
+        }
+    }
+    result?;
 
     info!("Graceful shutdown completed");
     Ok(())
 }
+
+/// Replace the current process with a fresh instance of the daemon, started
+/// with the same arguments. Only returns if the re-execution failed.
+pub fn restart_daemon() -> Error {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e.into(),
+    };
+    info!("Restarting daemon: {}", exe.display());
+    Command::new(exe)
+        .args(std::env::args_os().skip(1))
+        .exec()
+        .into()
+}
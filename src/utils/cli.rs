@@ -0,0 +1,65 @@
+use crate::error::{Error, Result};
+
+/// Command line options accepted by the daemon
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    /// Publish discovery, status and one round of metrics, then exit
+    pub once: bool,
+    /// Print the resolved configuration file path and exit
+    pub print_config_path: bool,
+    /// `--generate-config [path]`: write a commented config template to stdout
+    /// (`None`) or the given path, and exit
+    pub generate_config: Option<Option<String>>,
+    /// `--log-level <level>`: overrides `RUST_LOG` and the configured `log_level`
+    pub log_level: Option<String>,
+    /// `list-disks`: print the disks the daemon can see and exit
+    pub list_disks: bool,
+    /// `metrics`: print one round of system metrics as JSON and exit
+    pub metrics: bool,
+    /// `test-notification <summary> <message>`: send one desktop notification and exit
+    pub test_notification: Option<(String, String)>,
+}
+
+impl CliArgs {
+    /// Parse options from the process arguments
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    /// Parse options from an iterator of arguments (excluding the program name)
+    pub fn parse_from<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cli = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--once" => cli.once = true,
+                "--print-config-path" => cli.print_config_path = true,
+                "--generate-config" => {
+                    let path = args.next_if(|next| !next.starts_with('-'));
+                    cli.generate_config = Some(path);
+                }
+                "--log-level" => {
+                    let Some(level) = args.next() else {
+                        return Err(Error::Config("Usage: --log-level <level>".to_string()));
+                    };
+                    cli.log_level = Some(level);
+                }
+                "list-disks" => cli.list_disks = true,
+                "metrics" => cli.metrics = true,
+                "test-notification" => {
+                    let (Some(summary), Some(message)) = (args.next(), args.next()) else {
+                        return Err(Error::Config(
+                            "Usage: test-notification <summary> <message>".to_string(),
+                        ));
+                    };
+                    cli.test_notification = Some((summary, message));
+                }
+                other => return Err(Error::Config(format!("Unknown argument: {}", other))),
+            }
+        }
+        Ok(cli)
+    }
+}
@@ -1,10 +1,143 @@
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use crate::error::Result;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
-pub fn init_tracing(log_level: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Recent log lines kept for publishing over MQTT, set up by `init_tracing`
+static LOG_BUFFER: OnceLock<LogBuffer> = OnceLock::new();
+
+/// Ring buffer of the most recent formatted log lines
+#[derive(Debug)]
+pub struct LogBuffer {
+    capacity: usize,
+    lines: Mutex<VecDeque<String>>,
+    /// Also request a publish for every error-level event
+    publish_on_error: bool,
+    publish_requested: Notify,
+    publishing: AtomicBool,
+}
+
+impl LogBuffer {
+    fn new(capacity: usize, publish_on_error: bool) -> Self {
+        Self {
+            capacity,
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            publish_on_error,
+            publish_requested: Notify::new(),
+            publishing: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The buffered lines, oldest first
+    pub fn snapshot(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+
+    /// Mark a publish as running; returns false if one already is, so a burst
+    /// of errors while the broker is unreachable does not pile up publishes
+    pub fn begin_publish(&self) -> bool {
+        !self.publishing.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn end_publish(&self) {
+        self.publishing.store(false, Ordering::Release);
+    }
+}
+
+/// The log buffer, if `log_buffer_lines` is configured
+pub fn log_buffer() -> Option<&'static LogBuffer> {
+    LOG_BUFFER.get()
+}
+
+/// Ask the main loop to publish the buffered log lines
+pub fn request_log_publish() {
+    if let Some(buffer) = log_buffer() {
+        buffer.publish_requested.notify_one();
+    }
+}
+
+/// Resolves when a log publish was requested; never resolves without a log buffer
+pub async fn log_publish_requested() {
+    match log_buffer() {
+        Some(buffer) => buffer.publish_requested.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Collects the message and fields of an event into one line
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {}", value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+}
+
+/// Tracing layer feeding events into the log buffer
+struct LogBufferLayer(&'static LogBuffer);
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        let mut line = format!("{} {} {}:", timestamp, metadata.level(), metadata.target());
+        event.record(&mut LineVisitor(&mut line));
+        self.0.push(line);
+
+        if self.0.publish_on_error && *metadata.level() == Level::ERROR {
+            self.0.publish_requested.notify_one();
+        }
+    }
+}
+
+/// Initialize logging. With `log_buffer_lines`, the most recent lines are also
+/// kept in memory so they can be published over MQTT.
+pub fn init_tracing(
+    log_level: &str,
+    log_buffer_lines: Option<usize>,
+    publish_on_error: bool,
+) -> Result<()> {
     let filter = EnvFilter::try_new(log_level).or_else(|_| EnvFilter::try_new("info"))?;
 
+    let buffer_layer = log_buffer_lines.map(|capacity| {
+        LogBufferLayer(LOG_BUFFER.get_or_init(|| LogBuffer::new(capacity, publish_on_error)))
+    });
+
     tracing_subscriber::registry()
         .with(fmt::layer())
+        .with(buffer_layer)
         .with(filter)
         .init();
 
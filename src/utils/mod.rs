@@ -1,10 +1,16 @@
 // utils module - Contains utility modules for configuration, logging, and version information
 
+pub mod cli;
 pub mod config;
 pub mod logging;
 pub mod version;
 
 // Re-export commonly used items for convenience
-pub use config::{Button, Config, Switch};
+pub use cli::CliArgs;
+pub use config::{
+    Button, ButtonGroup, Config, CpuLoadMethod, CpuSource, DiscoveryMode, ExecMode,
+    MetricsMinDelta, MetricsPayloadFormat, MetricsTopicMode, MetricsUnit, NotifyEntity, Switch,
+    SwitchState,
+};
 pub use logging::init_tracing;
 pub use version::VersionInfo;